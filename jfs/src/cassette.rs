@@ -0,0 +1,362 @@
+// Recording and replaying of HTTP exchanges with JottaCloud.
+//
+// A cassette is a directory holding one pair of files per exchange:
+//   0000.json  method and uri of the request, status and content type of the response
+//   0000.body  raw response body, i.e. the XML as Jotta sent it
+//
+// Replay only checks method and uri, hence nothing else of the request is kept,
+// which keeps credentials and multipart boundaries out of the cassette, too.
+use actix_web::client::{ClientRequest, ClientResponse};
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::http::StatusCode;
+use actix_web::HttpMessage;
+use bytes::Bytes;
+use failure::Error;
+use futures::{future::result, Future};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// actix only reads 256 KiB of a response by default, restic's pack files
+// are bigger than that (and may be configured up to 128 MiB)
+const MAX_BODY_SIZE: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Fail)]
+pub enum CassetteError {
+    #[fail(display = "Cassette has no exchange number {}", index)]
+    Exhausted { index: usize },
+    #[fail(
+        display = "Cassette exchange {} was recorded for {} {}, but got {} {}",
+        index, recorded_method, recorded_uri, method, uri
+    )]
+    Mismatch {
+        index: usize,
+        recorded_method: String,
+        recorded_uri: String,
+        method: String,
+        uri: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Exchange {
+    method: String,
+    uri: String,
+    status: u16,
+    content_type: Option<String>,
+}
+
+/// A response, which has been read completely, no matter if it came from
+/// Jotta or from a cassette.
+#[derive(Debug)]
+pub struct RawResponse {
+    pub status: StatusCode,
+    pub content_type: Option<String>,
+    pub body: Bytes,
+}
+
+/// A directory of recorded exchanges. Clones share the same position,
+/// so that several clients may record into one cassette.
+#[derive(Clone, Debug)]
+pub struct Cassette {
+    dir: PathBuf,
+    position: Arc<AtomicUsize>,
+}
+
+impl Cassette {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Cassette {
+        Cassette {
+            dir: dir.as_ref().to_owned(),
+            position: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn next_index(&self) -> usize {
+        self.position.fetch_add(1, Ordering::SeqCst)
+    }
+
+    fn file(&self, index: usize, extension: &str) -> PathBuf {
+        self.dir.join(format!("{:04}.{}", index, extension))
+    }
+
+    fn record(&self, index: usize, request: &Exchange, body: &Bytes) -> Result<(), Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(
+            self.file(index, "json"),
+            serde_json::to_string_pretty(request)?,
+        )?;
+        std::fs::write(self.file(index, "body"), body)?;
+
+        debug!("Recorded exchange {} to {:?}", index, self.dir);
+        Ok(())
+    }
+
+    fn replay(&self, method: &str, uri: &str) -> Result<RawResponse, Error> {
+        let index = self.next_index();
+
+        let json = std::fs::read(self.file(index, "json"))
+            .map_err(|_| CassetteError::Exhausted { index })?;
+        let exchange: Exchange = serde_json::from_slice(&json)?;
+
+        if exchange.method != method || exchange.uri != uri {
+            return Err(CassetteError::Mismatch {
+                index,
+                recorded_method: exchange.method,
+                recorded_uri: exchange.uri,
+                method: method.to_owned(),
+                uri: uri.to_owned(),
+            }
+            .into());
+        }
+
+        let body = std::fs::read(self.file(index, "body"))?;
+
+        debug!("Replaying exchange {} from {:?}", index, self.dir);
+        Ok(RawResponse {
+            status: StatusCode::from_u16(exchange.status)?,
+            content_type: exchange.content_type,
+            body: Bytes::from(body),
+        })
+    }
+}
+
+/// How `JottaClient` talks to JottaCloud.
+#[derive(Debug)]
+pub enum Transport {
+    /// Send all requests to Jotta
+    Live,
+    /// Send all requests to Jotta and store the exchanges in a cassette
+    Record(Cassette),
+    /// Don't connect to Jotta at all, answer from a cassette instead
+    Replay(Cassette),
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Transport::Live
+    }
+}
+
+fn read_response(res: ClientResponse) -> impl Future<Item = RawResponse, Error = Error> {
+    let status = res.status();
+    let content_type = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_owned());
+
+    res.body()
+        .limit(MAX_BODY_SIZE)
+        .map_err(Error::from)
+        .map(move |body| RawResponse {
+            status,
+            content_type,
+            body,
+        })
+}
+
+impl Transport {
+    pub fn send(
+        &self,
+        request: ClientRequest,
+        timeout: Duration,
+    ) -> Box<dyn Future<Item = RawResponse, Error = Error>> {
+        let method = request.method().to_string();
+        let uri = request.uri().to_string();
+
+        match self {
+            Transport::Live => Box::new(
+                request
+                    .send()
+                    .timeout(timeout)
+                    .map_err(Error::from)
+                    .and_then(read_response),
+            ),
+            Transport::Record(cassette) => {
+                // Take the number before sending, so that replaying in
+                // request order works for concurrent requests, too.
+                let cassette = cassette.clone();
+                let index = cassette.next_index();
+
+                Box::new(
+                    request
+                        .send()
+                        .timeout(timeout)
+                        .map_err(Error::from)
+                        .and_then(read_response)
+                        .and_then(move |res| {
+                            let exchange = Exchange {
+                                method,
+                                uri,
+                                status: res.status.as_u16(),
+                                content_type: res.content_type.clone(),
+                            };
+                            cassette.record(index, &exchange, &res.body).map(|_| res)
+                        }),
+                )
+            }
+            Transport::Replay(cassette) => Box::new(result(cassette.replay(&method, &uri))),
+        }
+    }
+}
+
+#[test]
+fn test_replay() {
+    use crate::{JottaClient, Object};
+
+    let cassette = Cassette::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/fixtures/cassettes/query_object"
+    ));
+    let client = JottaClient::new("oleidinger", "secret")
+        .unwrap()
+        .with_transport(Transport::Replay(cassette));
+
+    match client
+        .query_object(&"/test".parse().unwrap())
+        .wait()
+        .unwrap()
+    {
+        Object::Folder(folder) => assert_eq!(folder.name, "test"),
+        Object::File(_) => panic!("Expected a folder"),
+    }
+
+    assert!(client
+        .query_object(&"/missing".parse().unwrap())
+        .wait()
+        .is_err());
+}
+
+#[test]
+fn test_replay_corrupt_download() {
+    use crate::{IntegrityError, JottaClient};
+
+    let cassette = Cassette::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/fixtures/cassettes/download_corrupt"
+    ));
+    let client = JottaClient::new("oleidinger", "secret")
+        .unwrap()
+        .with_transport(Transport::Replay(cassette));
+
+    let error = client
+        .download(&"/test/blupp.dat".parse().unwrap())
+        .wait()
+        .unwrap_err();
+    let integrity = error.downcast_ref::<IntegrityError>().unwrap();
+    assert_eq!(integrity.expected, "5c372a32c9ae748a4c040ebadc51a829");
+}
+
+#[cfg(test)]
+fn replay_client(name: &str) -> crate::JottaClient {
+    let cassette = Cassette::new(
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/cassettes")
+            .join(name),
+    );
+    crate::JottaClient::new("oleidinger", "secret")
+        .unwrap()
+        .with_transport(Transport::Replay(cassette))
+}
+
+#[cfg(test)]
+fn replay_upload(name: &str) -> Result<crate::Object, Error> {
+    let data = futures::stream::once(Ok::<_, Error>(Bytes::from_static(b"Hallo Welt")));
+
+    replay_client(name)
+        .upload(&"/test/blupp.dat".parse().unwrap(), data)
+        .wait()
+}
+
+#[test]
+fn test_replay_upload() {
+    use crate::Object;
+
+    // Identical content at the path: no further request, the cassette would be exhausted.
+    // Known by md5: created by cphash only. Unknown: the data is sent.
+    for name in &["upload_identical", "upload_dedup", "upload_miss"] {
+        match replay_upload(name).unwrap() {
+            Object::File(f) => assert_eq!(f.md5(), Some("5c372a32c9ae748a4c040ebadc51a829")),
+            Object::Folder(_) => panic!("Expected a file"),
+        }
+    }
+}
+
+#[test]
+fn test_replay_resume() {
+    use crate::{resume_offset, File, Object};
+
+    // Jotta holds 4 bytes of the same content: only the rest is sent, without asking
+    // for the content by md5, which would be a request to another URL.
+    match replay_upload("upload_resume").unwrap() {
+        Object::File(f) => assert!(f.is_complete()),
+        Object::Folder(_) => panic!("Expected a file"),
+    }
+
+    let incomplete = |md5: &str, size: usize| {
+        format!(
+            r#"<file name="blupp.dat" uuid="1502fdd0-c24e-4acc-984a-7d1e05059ccd">
+                <latestRevision>
+                    <number>1</number>
+                    <state>INCOMPLETE</state>
+                    <created>2019-01-20-T10:01:03Z</created>
+                    <modified>2019-01-20-T10:01:03Z</modified>
+                    <size>{}</size>
+                    <md5>{}</md5>
+                    <updated>2019-01-20-T10:01:03Z</updated>
+                </latestRevision>
+            </file>"#,
+            size, md5
+        )
+        .parse::<File>()
+        .unwrap()
+    };
+    let md5 = "5c372a32c9ae748a4c040ebadc51a829";
+
+    assert_eq!(resume_offset(&incomplete(md5, 4), 10, md5), Some(4));
+    // Other content, nothing to resume at, or nothing left to send
+    assert_eq!(resume_offset(&incomplete(md5, 4), 10, "other"), None);
+    assert_eq!(resume_offset(&incomplete(md5, 0), 10, md5), None);
+    assert_eq!(resume_offset(&incomplete(md5, 10), 10, md5), None);
+}
+
+#[test]
+fn test_replay_upload_unauthorized() {
+    use crate::JottaError;
+
+    let error = replay_upload("upload_unauthorized").unwrap_err();
+    let jotta_error = error.find_root_cause().downcast_ref::<JottaError>();
+    assert_eq!(jotta_error.unwrap().code, 401);
+}
+
+#[test]
+fn test_replay_incomplete() {
+    use crate::Object;
+
+    let incomplete = replay_client("list_incomplete")
+        .list_incomplete(&"/test".parse().unwrap())
+        .wait()
+        .unwrap();
+    let names = incomplete
+        .iter()
+        .map(|f| f.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["stale.dat"]);
+
+    let client = replay_client("abandon_incomplete");
+    match client
+        .abandon_incomplete(&"/test/stale.dat".parse().unwrap())
+        .wait()
+        .unwrap()
+    {
+        Object::File(f) => assert!(f.deleted.is_some()),
+        Object::Folder(_) => panic!("Expected a file"),
+    }
+
+    // Has a complete revision, hence no delete request
+    assert!(client
+        .abandon_incomplete(&"/test/blupp.dat".parse().unwrap())
+        .wait()
+        .is_err());
+}
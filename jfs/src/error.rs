@@ -1,7 +1,9 @@
 use crate::fromxml::FromXml;
+use crate::toxml::*;
 use crate::util::*;
 use quick_xml::events::{attributes::Attributes, Event};
 use quick_xml::Reader;
+use std::collections::BTreeMap;
 use std::io::BufRead;
 use std::str::FromStr;
 
@@ -13,7 +15,7 @@ pub enum JfsXmlError {
     UnexpectedTag { tag: String },
 }
 
-#[derive(Default, Debug, Fail)]
+#[derive(Clone, Default, Debug, Fail, Serialize, Deserialize)]
 #[fail(
     display = "Jottacloud sent error code {}: {}, {}.",
     code, message, reason
@@ -22,6 +24,41 @@ pub struct JottaError {
     pub message: String,
     pub reason: String,
     pub code: usize,
+    pub cause: String,
+    pub hostname: String,
+    pub x_id: String,
+    /// Elements we don't know (yet), by tag name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
+}
+
+impl JottaError {
+    /// Whether the upload failed, because the account has no space left
+    pub fn is_quota_exceeded(&self) -> bool {
+        self.code == 507
+            || [&self.message, &self.reason, &self.cause]
+                .iter()
+                .any(|text| text.to_lowercase().contains("quota"))
+    }
+}
+
+/// The data we got (or sent) doesn't match the md5 sum Jotta reports.
+#[derive(Debug, Fail)]
+#[fail(
+    display = "Integrity check of {} failed: expected md5 {}, got {}.",
+    path, expected, actual
+)]
+pub struct IntegrityError {
+    pub path: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// The file exists, but none of its revisions has been uploaded completely.
+#[derive(Debug, Fail)]
+#[fail(display = "{} has no completely uploaded revision.", path)]
+pub struct IncompleteError {
+    pub path: String,
 }
 
 #[derive(Debug, Fail)]
@@ -70,20 +107,14 @@ impl FromXml for JottaError {
                             v.reason = element_text(reader)?
                                 .ok_or(err_msg("Couldn't pase field reason of error"))?
                         }
-                        b"currentRevision" => (), // ignore begin of revision tag
                         b"code" => {
                             v.code = element_usize(reader)?
                                 .ok_or(err_msg("Couldn't parse field code of error"))?
                         }
-                        b"cause" => (),
-                        b"hostname" => (),
-                        b"x-id" => (),
-                        n => {
-                            return Err(JfsXmlError::UnexpectedTag {
-                                tag: from_utf8(n)?.to_owned(),
-                            }
-                            .into());
-                        }
+                        b"cause" => v.cause = element_text(reader)?.unwrap_or_default(),
+                        b"hostname" => v.hostname = element_text(reader)?.unwrap_or_default(),
+                        b"x-id" => v.x_id = element_text(reader)?.unwrap_or_default(),
+                        n => element_unknown(reader, n, &mut v.extra)?,
                     }
                 }
                 Event::End(element) => {
@@ -95,6 +126,11 @@ impl FromXml for JottaError {
                         break;
                     }
                 }
+                Event::Empty(element) => match element.name() {
+                    // known, but without content
+                    b"message" | b"reason" | b"code" | b"cause" | b"hostname" | b"x-id" => (),
+                    n => element_unknown_empty(n, &mut v.extra)?,
+                },
                 Event::Eof => return Err(JfsXmlError::UnexpectedEndOfFile.into()),
                 _ => {}
             }
@@ -106,11 +142,27 @@ impl FromXml for JottaError {
     }
 }
 
+impl ToXml for JottaError {
+    fn write_xml(&self, out: &mut String) {
+        write_start(out, Self::TAG, &[]);
+        write_element(out, "code", &self.code);
+        write_element(out, "message", &self.message);
+        write_element(out, "reason", &self.reason);
+        write_element(out, "cause", &self.cause);
+        write_element(out, "hostname", &self.hostname);
+        write_element(out, "x-id", &self.x_id);
+        write_extra(out, &self.extra);
+        write_end(out, Self::TAG);
+    }
+}
+
 impl_from_str!(JottaError);
 
 #[test]
 fn test_401() {
-    let _error = r#"
+    crate::fromxml::set_strict_parsing(true);
+
+    let error = r#"
     <error>
         <code>401</code>
         <message>org.springframework.security.authentication.BadCredentialsException: Bad credentials</message>
@@ -119,10 +171,17 @@ fn test_401() {
         <hostname>dn-125</hostname>
         <x-id>096492164813</x-id>
     </error>"#.parse::<JottaError>().unwrap();
+
+    let again = error.to_xml().parse::<JottaError>().unwrap();
+    assert_eq!(again.code, 401);
+    assert_eq!(again.hostname, "dn-125");
+    assert_eq!(again.x_id, "096492164813");
 }
 
 #[test]
 fn test_404() {
+    crate::fromxml::set_strict_parsing(true);
+
     let _e404 = r#"
     <error>
     <code>404</code>
@@ -136,3 +195,25 @@ fn test_404() {
     .parse::<JottaError>()
     .unwrap();
 }
+
+#[test]
+fn test_quota_exceeded() {
+    let full = JottaError {
+        code: 507,
+        ..Default::default()
+    };
+    assert!(full.is_quota_exceeded());
+
+    let full = JottaError {
+        code: 400,
+        message: "no.jotta.backup.errors.QuotaExceededException".to_owned(),
+        ..Default::default()
+    };
+    assert!(full.is_quota_exceeded());
+
+    let not_found = JottaError {
+        code: 404,
+        ..Default::default()
+    };
+    assert!(!not_found.is_quota_exceeded());
+}
@@ -1,38 +1,58 @@
 use crate::error::JfsXmlError;
 use crate::fromxml::*;
+use crate::revision::Revision;
+use crate::toxml::*;
 use crate::util::*;
 use failure::Error;
-use mime::Mime;
 use quick_xml::events::{attributes::Attributes, Event};
 use quick_xml::Reader;
+use std::collections::BTreeMap;
 use std::io::BufRead;
 use std::str::FromStr;
 
-// TODO: Get rid of the options... currently, they are only there to get Default
-#[derive(Default, Debug, Serialize)]
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct File {
     pub name: String,
     pub uuid: String,
     pub request_time: Option<TimeStamp>,
-    //path: Option<String>, // not present in folder
+    pub path: Option<String>,    // not present in folder
     pub abspath: Option<String>, // not present in folder
-    pub revision: usize,
-    pub state: Option<TransferState>,
-    pub created: Option<TimeStamp>,
     pub deleted: Option<TimeStamp>,
-    pub modified: Option<TimeStamp>,
-    #[serde(skip_serializing)]
-    pub mime: Option<Mime>,
-    pub size: usize,
-    pub md5: String,
-    pub updated: Option<TimeStamp>,
+    /// The last completely uploaded revision
+    pub current_revision: Option<Revision>,
+    /// Only present, if there is a newer, incomplete upload
+    pub latest_revision: Option<Revision>,
+    /// Older revisions; only present, if the file was queried directly
+    #[serde(default)]
+    pub revisions: Vec<Revision>,
+    /// Elements we don't know (yet), by tag name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
+}
+
+impl File {
+    /// The size of the current revision, 0 if there is none.
+    pub fn size(&self) -> usize {
+        self.current_revision.as_ref().map_or(0, |r| r.size)
+    }
+
+    /// The md5 sum of the current revision.
+    pub fn md5(&self) -> Option<&str> {
+        self.current_revision.as_ref().map(|r| r.md5.as_str())
+    }
+
+    /// Whether there is a completely uploaded revision of this file
+    pub fn is_complete(&self) -> bool {
+        self.current_revision
+            .as_ref()
+            .map_or(false, Revision::is_complete)
+    }
 }
 
 impl FromXml for File {
     const TAG: &'static str = "file";
 
     fn from_xml<R: BufRead>(reader: &mut Reader<R>, attrs: Attributes) -> Result<Self, Error> {
-        use failure::err_msg;
         use std::str::from_utf8;
 
         let mut file = File::default();
@@ -41,7 +61,7 @@ impl FromXml for File {
         for attr in attrs {
             let a = attr?;
             match a.key {
-                b"name" => file.name = from_utf8(&a.value)?.to_owned(),
+                b"name" => file.name = from_utf8(&a.unescaped_value()?)?.to_owned(),
                 b"uuid" => file.uuid = from_utf8(&a.value)?.to_owned(),
                 b"time" => file.request_time = Some(parse_jotta_timestamp(from_utf8(&a.value)?)?),
                 b"deleted" => file.deleted = Some(parse_jotta_timestamp(from_utf8(&a.value)?)?),
@@ -55,35 +75,17 @@ impl FromXml for File {
                     debug!("New element: {}", from_utf8(element.name())?);
                     match element.name() {
                         b"abspath" => file.abspath = element_text(reader)?,
-                        b"path" => (),            // same as abspath, hence ignore
-                        b"currentRevision" => (), // ignore begin of revision tag
-                        b"revisions" => {
-                            break;
-                        } // TODO: this will fail, if they ever change order.
-                        b"latestRevision" => (),  // same for incomplete file
-                        b"number" => {
-                            file.revision = element_usize(reader)?
-                                .ok_or(err_msg("Couldn't parse revision of file"))?
-                        }
-                        b"state" => file.state = element_transfer_state(reader)?,
-                        b"created" => file.created = element_timestamp(reader)?,
-                        b"modified" => file.modified = element_timestamp(reader)?,
-                        b"size" => {
-                            file.size = element_usize(reader)?
-                                .ok_or(err_msg("Couldn't parse size of file"))?
+                        b"path" => file.path = element_text(reader)?,
+                        b"currentRevision" => {
+                            file.current_revision =
+                                Some(Revision::from_xml(reader, element.attributes())?)
                         }
-                        b"mime" => file.mime = element_mime(reader)?,
-                        b"md5" => {
-                            file.md5 = element_text(reader)?
-                                .ok_or(err_msg("Couldn't parse md5 sum of file"))?
-                        }
-                        b"updated" => file.updated = element_timestamp(reader)?,
-                        n => {
-                            return Err(JfsXmlError::UnexpectedTag {
-                                tag: from_utf8(n)?.to_owned(),
-                            }
-                            .into());
+                        b"latestRevision" => {
+                            file.latest_revision =
+                                Some(Revision::from_xml(reader, element.attributes())?)
                         }
+                        b"revisions" => file.revisions = parse_list(reader, b"revisions")?,
+                        n => element_unknown(reader, n, &mut file.extra)?,
                     }
                 }
                 Event::End(element) => {
@@ -95,6 +97,12 @@ impl FromXml for File {
                         break;
                     }
                 }
+                Event::Empty(element) => match element.name() {
+                    // known, but without content
+                    b"abspath" | b"path" | b"currentRevision" | b"latestRevision"
+                    | b"revisions" => (),
+                    n => element_unknown_empty(n, &mut file.extra)?,
+                },
                 Event::Eof => return Err(JfsXmlError::UnexpectedEndOfFile.into()),
                 _ => {}
             }
@@ -106,11 +114,44 @@ impl FromXml for File {
     }
 }
 
+impl ToXml for File {
+    fn write_xml(&self, out: &mut String) {
+        write_start(
+            out,
+            Self::TAG,
+            &[
+                ("name", Some(self.name.clone())),
+                ("uuid", Some(self.uuid.clone())),
+                ("deleted", self.deleted.as_ref().map(format_jotta_timestamp)),
+                (
+                    "time",
+                    self.request_time.as_ref().map(format_jotta_timestamp),
+                ),
+            ],
+        );
+        write_optional_element(out, "path", &self.path);
+        write_optional_element(out, "abspath", &self.abspath);
+        if let Some(ref revision) = self.current_revision {
+            revision.write_xml_as(out, "currentRevision");
+        }
+        if let Some(ref revision) = self.latest_revision {
+            revision.write_xml_as(out, "latestRevision");
+        }
+        if !self.revisions.is_empty() {
+            write_list(out, "revisions", &self.revisions);
+        }
+        write_extra(out, &self.extra);
+        write_end(out, Self::TAG);
+    }
+}
+
 impl_from_str!(File);
 
 #[test]
 fn test_from_str() {
-    let _file = r#"
+    crate::fromxml::set_strict_parsing(true);
+
+    let file = r#"
 <file name="blupp.dat" uuid="1502fdd0-c24e-4acc-984a-7d1e05059ccd" time="2019-01-20-T10:03:54Z" host="Backup2-backup2-get-oldgluster-dp1-2">
   <path xml:space="preserve">/oleidinger/Jotta/Sync/test</path>
   <abspath xml:space="preserve">/oleidinger/Jotta/Sync/test</abspath>
@@ -148,4 +189,109 @@ fn test_from_str() {
   </revisions>
 </file>
 "#.parse::<File>().unwrap();
+
+    assert_eq!(file.current_revision.as_ref().map(|r| r.number), Some(3));
+    assert_eq!(file.revisions.len(), 2);
+    assert_eq!(file.size(), 10);
+}
+
+#[test]
+fn test_unknown_tag() {
+    let xml = r#"
+<file name="blupp.dat" uuid="1502fdd0-c24e-4acc-984a-7d1e05059ccd">
+  <currentRevision>
+    <number>1</number>
+    <state>COMPLETED</state>
+    <created>2019-01-20-T10:01:03Z</created>
+    <modified>2019-01-20-T10:01:03Z</modified>
+    <size>10</size>
+    <md5>5c372a32c9ae748a4c040ebadc51a829</md5>
+    <checksum-sha1>8cba6bfa1e71b1d2d4e4c2c34b2efc4f53a6b7b7</checksum-sha1>
+    <updated>2019-01-20-T10:01:03Z</updated>
+  </currentRevision>
+</file>
+"#;
+
+    crate::fromxml::set_strict_parsing(true);
+    assert!(xml.parse::<File>().is_err());
+
+    crate::fromxml::set_strict_parsing(false);
+    let file = xml.parse::<File>().unwrap();
+    let revision = file.current_revision.unwrap();
+    assert_eq!(revision.size, 10);
+    assert_eq!(
+        revision.extra.get("checksum-sha1").map(String::as_str),
+        Some("8cba6bfa1e71b1d2d4e4c2c34b2efc4f53a6b7b7")
+    );
+}
+
+#[test]
+fn test_unknown_empty_tag() {
+    let xml = r#"<file name="blupp.dat" uuid="1502fdd0-c24e-4acc-984a-7d1e05059ccd"><shared/><currentRevision><number>1</number><state>COMPLETED</state><created>2019-01-20-T10:01:03Z</created><modified>2019-01-20-T10:01:03Z</modified><size>10</size><md5>5c372a32c9ae748a4c040ebadc51a829</md5><pinned/><updated>2019-01-20-T10:01:03Z</updated></currentRevision></file>"#;
+
+    // FromStr expands empty elements, a reader may as well report them as such
+    let parse_unexpanded = || {
+        let mut reader = Reader::from_str(xml);
+        let mut buf = Vec::new();
+        match reader.read_event(&mut buf).unwrap() {
+            Event::Start(element) => File::from_xml(&mut reader, element.attributes()),
+            _ => panic!("Expected the file tag"),
+        }
+    };
+
+    crate::fromxml::set_strict_parsing(true);
+    assert!(xml.parse::<File>().is_err());
+    assert!(parse_unexpanded().is_err());
+
+    crate::fromxml::set_strict_parsing(false);
+    for file in vec![xml.parse::<File>().unwrap(), parse_unexpanded().unwrap()] {
+        assert_eq!(file.extra.get("shared").map(String::as_str), Some(""));
+        let revision = file.current_revision.unwrap();
+        assert_eq!(revision.size, 10);
+        assert_eq!(revision.extra.get("pinned").map(String::as_str), Some(""));
+    }
+}
+
+#[test]
+fn test_roundtrip() {
+    crate::fromxml::set_strict_parsing(true);
+
+    let xml = r#"
+<file name="a &amp; b.dat" uuid="1502fdd0-c24e-4acc-984a-7d1e05059ccd">
+  <currentRevision>
+    <number>2</number>
+    <state>COMPLETED</state>
+    <created>2019-01-20-T10:01:03Z</created>
+    <modified>2019-01-20-T10:01:03Z</modified>
+    <mime>application/octet-stream</mime>
+    <size>10</size>
+    <md5>5c372a32c9ae748a4c040ebadc51a829</md5>
+    <updated>2019-01-20-T10:01:03Z</updated>
+  </currentRevision>
+  <latestRevision>
+    <number>3</number>
+    <state>INCOMPLETE</state>
+    <created>2019-01-21-T10:01:03Z</created>
+    <modified>2019-01-21-T10:01:03Z</modified>
+    <mime>application/octet-stream</mime>
+    <md5>e1dc5bc4f2bec6bf866a0a463eb5c239</md5>
+    <updated>2019-01-21-T10:01:03Z</updated>
+  </latestRevision>
+</file>
+"#;
+    let file = xml.parse::<File>().unwrap();
+
+    // XML
+    let again = file.to_xml().parse::<File>().unwrap();
+    assert_eq!(again.name, "a & b.dat");
+    assert_eq!(again.md5(), Some("5c372a32c9ae748a4c040ebadc51a829"));
+    assert_eq!(
+        again.latest_revision.map(|r| r.state),
+        Some(TransferState::Incomplete)
+    );
+
+    // serde, i.e. for caching on disk
+    let json = serde_json::to_string(&file).unwrap();
+    let again: File = serde_json::from_str(&json).unwrap();
+    assert_eq!(again.to_xml(), file.to_xml());
 }
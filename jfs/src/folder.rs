@@ -1,22 +1,29 @@
 use crate::error::JfsXmlError;
 use crate::file::File;
 use crate::fromxml::FromXml;
+use crate::toxml::*;
 use crate::util::*;
 use failure::Error;
 use quick_xml::events::{attributes::Attributes, Event};
 use quick_xml::Reader;
+use std::collections::BTreeMap;
 use std::io::BufRead;
 use std::str::FromStr;
 
-#[derive(Default, Debug, Serialize)]
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct Folder {
     pub name: String,
     pub request_time: Option<TimeStamp>,
     pub deleted: Option<TimeStamp>,
-    //path: String,
+    pub path: Option<String>,
     pub abspath: Option<String>, // sometimes available, i.e. in subfolders
+    #[serde(default)]
     pub files: Vec<File>,
+    #[serde(default)]
     pub folders: Vec<Folder>,
+    /// Elements we don't know (yet), by tag name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
 }
 
 impl FromXml for Folder {
@@ -31,7 +38,7 @@ impl FromXml for Folder {
         for attr in attrs {
             let a = attr.unwrap();
             match a.key {
-                b"name" => file.name = from_utf8(&a.value)?.to_owned(),
+                b"name" => file.name = from_utf8(&a.unescaped_value()?)?.to_owned(),
                 b"deleted" => file.deleted = Some(parse_jotta_timestamp(from_utf8(&a.value)?)?),
                 b"time" => file.request_time = Some(parse_jotta_timestamp(from_utf8(&a.value)?)?),
                 b"host" => (), // ignored
@@ -43,17 +50,12 @@ impl FromXml for Folder {
                 Event::Start(element) => {
                     debug!("New element: {}", from_utf8(element.name()).unwrap());
                     match element.name() {
-                        b"path" => (),
+                        b"path" => file.path = element_text(reader)?,
                         b"abspath" => file.abspath = element_text(reader)?,
                         b"folders" => file.folders = parse_list(reader, b"folders")?,
                         b"files" => file.files = parse_list(reader, b"files")?,
                         b"metadata" => (), // not really useful, ignore for now
-                        n => {
-                            return Err(JfsXmlError::UnexpectedTag {
-                                tag: from_utf8(n)?.to_owned(),
-                            }
-                            .into());
-                        }
+                        n => element_unknown(reader, n, &mut file.extra)?,
                     }
                 }
                 Event::End(element) => {
@@ -68,6 +70,11 @@ impl FromXml for Folder {
                         break;
                     }
                 }
+                Event::Empty(element) => match element.name() {
+                    // known, but without content
+                    b"path" | b"abspath" | b"folders" | b"files" | b"metadata" => (),
+                    n => element_unknown_empty(n, &mut file.extra)?,
+                },
                 Event::Eof => return Err(JfsXmlError::UnexpectedEndOfFile.into()),
                 _ => {}
             }
@@ -79,11 +86,36 @@ impl FromXml for Folder {
     }
 }
 
+impl ToXml for Folder {
+    fn write_xml(&self, out: &mut String) {
+        write_start(
+            out,
+            Self::TAG,
+            &[
+                ("name", Some(self.name.clone())),
+                ("deleted", self.deleted.as_ref().map(format_jotta_timestamp)),
+                (
+                    "time",
+                    self.request_time.as_ref().map(format_jotta_timestamp),
+                ),
+            ],
+        );
+        write_optional_element(out, "path", &self.path);
+        write_optional_element(out, "abspath", &self.abspath);
+        write_list(out, "folders", &self.folders);
+        write_list(out, "files", &self.files);
+        write_extra(out, &self.extra);
+        write_end(out, Self::TAG);
+    }
+}
+
 impl_from_str!(Folder);
 
 #[test]
 fn test_from_str() {
-    let _folder = r#"
+    crate::fromxml::set_strict_parsing(true);
+
+    let folder = r#"
         <folder name="data" time="2018-05-24-T19:50:45Z" host="dn-157">
             <path xml:space="preserve">/oleidinger/Jotta/Sync/test123</path>
             <abspath xml:space="preserve">/oleidinger/Jotta/Sync/test123</abspath>
@@ -124,4 +156,15 @@ fn test_from_str() {
             </files>
             <metadata first="" max="" total="8" num_folders="6" num_files="2"/>
         </folder>"#.parse::<Folder>().unwrap();
+
+    assert_eq!(folder.folders.len(), 6);
+    assert!(folder.files[0].is_complete());
+    assert!(!folder.files[1].is_complete());
+
+    let again = folder.to_xml().parse::<Folder>().unwrap();
+    assert_eq!(again.folders[0].deleted, folder.folders[0].deleted);
+    assert_eq!(
+        again.files[1].latest_revision.as_ref().map(|r| r.number),
+        Some(1)
+    );
 }
@@ -4,6 +4,7 @@
 //
 // This program is free software; you can redistribute it and/or modify
 // it under the terms of the MIT License and/or Apache 2.0 License.
+use std::cell::Cell;
 use std::io::BufRead;
 
 use quick_xml::events::attributes::Attributes;
@@ -11,6 +12,25 @@ use quick_xml::Reader;
 
 use failure::Error;
 
+thread_local! {
+    static STRICT_PARSING: Cell<bool> = Cell::new(false);
+}
+
+/// In strict mode, parsing fails on any element we don't know. Otherwise,
+/// unknown elements are collected into the `extra` maps of the parsed types.
+///
+/// The setting is per thread, as parsing happens synchronously anyway; this
+/// way tests can't interfere with each other.
+pub fn set_strict_parsing(strict: bool) {
+    STRICT_PARSING.with(|s| s.set(strict));
+}
+
+pub fn strict_parsing() -> bool {
+    STRICT_PARSING.with(|s| s.get())
+}
+
+/// Parses the element, whose start tag the reader just returned. Unknown
+/// elements are handled, no matter if the reader expands empty ones.
 pub trait FromXml: Sized {
     const TAG: &'static str;
 
@@ -1,6 +1,9 @@
 #[macro_use]
 extern crate failure;
 
+#[macro_use]
+extern crate lazy_static;
+
 #[macro_use]
 extern crate log;
 
@@ -10,17 +13,28 @@ extern crate serde_derive;
 #[macro_use]
 mod fromxml;
 
+mod cassette;
 mod error;
 mod file;
 mod folder;
 mod object;
+mod path;
+mod revision;
+mod toxml;
+mod user;
 mod util;
 
-pub use crate::error::{JfsXmlError, JottaError};
+pub use crate::cassette::{Cassette, CassetteError, RawResponse, Transport};
+pub use crate::error::{IncompleteError, IntegrityError, JfsXmlError, JottaError};
 pub use crate::file::File;
 pub use crate::folder::Folder;
-pub use crate::fromxml::FromXml;
+pub use crate::fromxml::{set_strict_parsing, FromXml};
 pub use crate::object::Object;
+pub use crate::path::{JottaPath, JottaPathError};
+pub use crate::revision::Revision;
+pub use crate::toxml::ToXml;
+pub use crate::user::{Device, User};
+pub use crate::util::{TimeStamp, TransferState};
 
 use actix_web::client;
 use actix_web::http::header::AUTHORIZATION;
@@ -33,15 +47,65 @@ use futures::{
     Future, Stream,
 };
 
+use std::sync::Arc;
+use std::time::Duration;
+
+// actix' default for client requests
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(600); // TODO: is there a better way?
+
+// Downloads carry whole objects, i.e. restic's packs, just like uploads
+const DOWNLOAD_TIMEOUT: Duration = UPLOAD_TIMEOUT;
+
+/// What `upload` finds at the target path before sending any data
+enum Existing {
+    /// The same content is stored already
+    Identical(File),
+    /// An earlier upload of the same content stopped after the given number of bytes
+    Incomplete(usize),
+    Nothing,
+}
+
+#[derive(Clone)]
 pub struct JottaClient {
     authorization: String,
+    /// Where Jotta has the account information, see `user`
+    account_url: String,
     base_url: String,
-    username: String,
+    upload_url: String,
+    mount_point: JottaPath,
+    transport: Arc<Transport>,
+    verify_uploads: bool,
 }
 
 use std::fmt::Debug;
 use std::str::FromStr;
 
+/// Whether Jotta answered with 404, i.e. there is nothing at the path
+pub fn is_not_found(error: &failure::Error) -> bool {
+    error
+        .find_root_cause()
+        .downcast_ref::<JottaError>()
+        .map_or(false, |e| e.code == 404)
+}
+
+/// Where to resume an incomplete upload of content with the given size and md5 sum to file.
+/// Jotta reports how much of an incomplete revision it has, when asked for the file itself.
+fn resume_offset(file: &File, size: usize, digest: &str) -> Option<usize> {
+    let revision = file.latest_revision.as_ref()?;
+
+    if file.deleted.is_none()
+        && !revision.is_complete()
+        && revision.md5 == digest
+        && revision.size > 0
+        && revision.size < size
+    {
+        Some(revision.size)
+    } else {
+        None
+    }
+}
+
 fn parse_xml<T: FromStr + Debug>(xml: &str) -> Result<T, failure::Error>
 where
     <T as std::str::FromStr>::Err: std::fmt::Debug,
@@ -55,196 +119,521 @@ where
 }
 
 impl JottaClient {
-    pub fn new(username: &str, password: &str) -> JottaClient {
+    /// Fails for a username, which isn't a valid path segment, as all
+    /// requests go to paths below it
+    pub fn new(username: &str, password: &str) -> Result<JottaClient, failure::Error> {
         let user_and_password = format!("{}:{}", username, password);
         let authorization = format!("Basic {}", base64::encode(&user_and_password));
 
+        let account = JottaPath::root().join(username)?;
+        let account_url = format!("https://www.jottacloud.com/jfs{}", account.encoded());
+
         // It would seem, currently only Archive, Shared and Sync are supported as "mount points"
-        let base_url = format!("https://www.jottacloud.com/jfs/{}/Jotta/Sync", username);
+        let mount_point = account.join("Jotta")?.join("Sync")?;
+        let base_url = format!("https://www.jottacloud.com/jfs{}", mount_point.encoded());
+        let upload_url = format!("https://up.jottacloud.com/jfs{}", mount_point.encoded());
 
-        JottaClient {
+        Ok(JottaClient {
             authorization,
+            account_url,
             base_url,
-            username: username.to_owned(),
-        }
+            upload_url,
+            mount_point,
+            transport: Arc::new(Transport::default()),
+            verify_uploads: true,
+        })
     }
 
-    fn handle_client_response(
-        res: client::ClientResponse,
-    ) -> impl Future<Item = Object, Error = failure::Error> {
-        use actix_web::HttpMessage;
-        use hyper::header::CONTENT_TYPE;
+    /// Check the md5 sum of the file Jotta returns after an upload (default: on)
+    pub fn verify_uploads(mut self, verify: bool) -> JottaClient {
+        self.verify_uploads = verify;
+        self
+    }
 
-        debug!("handle_client_response status: {}", res.status());
+    /// Use the given transport instead of talking to Jotta directly,
+    /// i.e. to record or replay a session.
+    pub fn with_transport(mut self, transport: Transport) -> JottaClient {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    fn handle_client_response(res: RawResponse) -> Result<Object, failure::Error> {
+        debug!("handle_client_response status: {}", res.status);
 
         // matches i.e. application/xml and text/xml
-        if res.headers().get(CONTENT_TYPE).map_or_else(
+        if res.content_type.as_ref().map_or_else(
             || false, // TODO: do we need to support different cases, i.e. upper case here?
-            |v| v.as_bytes() == b"text/xml" || v.as_bytes() == b"application/xml",
+            |v| v == "text/xml" || v == "application/xml",
         ) {
-            ok(res)
+            // Not via parse_xml, as that would hide a JottaError inside the message
+            let result = std::str::from_utf8(&res.body)?.parse::<Object>();
+            debug!("Parsing resulted in {:?}", result);
+            result
         } else {
-            err(err_msg(format!(
+            Err(err_msg(format!(
                 "Expected ContentType {:?} not found!",
                 mime::TEXT_XML
             )))
         }
-        .and_then(|res| {
-            res.body()
-                .into_stream()
-                .concat2()
-                .map_err(failure::Error::from)
-        })
-        .and_then(|ref body_bytes| parse_xml::<Object>(std::str::from_utf8(body_bytes)?))
-        .map_err(failure::Error::from)
     }
 
-    pub fn query_object(&self, path: &str) -> impl Future<Item = Object, Error = failure::Error> {
+    fn url(&self, path: &JottaPath) -> String {
         let mut full_uri = self.base_url.clone();
-        full_uri.push_str(path);
+        full_uri.push_str(&path.encoded());
+        full_uri
+    }
+
+    /// The account information, i.e. the capacity and how much of it is used
+    pub fn user(&self) -> impl Future<Item = User, Error = failure::Error> {
+        use hyper::http::StatusCode;
+
+        debug!("user via {}", self.account_url);
+        let http_request = client::ClientRequest::get(self.account_url.as_str())
+            .header(AUTHORIZATION, self.authorization.as_str())
+            .finish()
+            .unwrap();
+
+        self.transport
+            .send(http_request, REQUEST_TIMEOUT)
+            .and_then(|res| {
+                let xml = std::str::from_utf8(&res.body)?;
+                if res.status == StatusCode::OK {
+                    parse_xml::<User>(xml)
+                } else {
+                    Err(parse_xml::<JottaError>(xml)
+                        .map(failure::Error::from)
+                        .unwrap_or_else(|e| e))
+                }
+            })
+    }
+
+    pub fn query_object(
+        &self,
+        path: &JottaPath,
+    ) -> impl Future<Item = Object, Error = failure::Error> {
+        let full_uri = self.url(path);
 
         debug!("query_object via {}", full_uri);
         let http_request = client::ClientRequest::get(full_uri)
             .header(AUTHORIZATION, self.authorization.as_str())
             .finish()
-            .unwrap()
-            .send();
+            .unwrap();
 
-        http_request
-            .map_err(failure::Error::from)
+        self.transport
+            .send(http_request, REQUEST_TIMEOUT)
             .and_then(JottaClient::handle_client_response)
     }
 
-    pub fn list(&self, path: &str) -> impl Future<Item = Folder, Error = failure::Error> {
+    pub fn list(&self, path: &JottaPath) -> impl Future<Item = Folder, Error = failure::Error> {
         self.query_object(path).and_then(|obj| match obj {
             Object::Folder(dir) => ok(dir),
             Object::File(_) => err(err_msg("Not a directory")),
         })
     }
 
+    /// Uploads data to the given path. Nothing is transferred, if the file
+    /// already exists with the same content, or if Jotta knows the content
+    /// from elsewhere (by md5 sum). An incomplete earlier upload of the same
+    /// content is resumed.
     pub fn upload<S>(
         &self,
-        path: &str,
+        path: &JottaPath,
         data: S,
     ) -> impl Future<Item = Object, Error = failure::Error>
     where
         S: Stream<Item = Bytes, Error = failure::Error> + 'static,
     {
+        use futures::future::Either;
         use futures::Stream;
         use md5;
-        use mpart_async::{ByteStream, MultipartRequest};
 
         // TODO: find out, if there is a way to do a streaming upload;
         // this API needs the total file size and some md5 sum, which we don't
         // know in advance, of course.
 
-        // URL is   https://up.jottacloud.com/jfs/[...]
-
-        let mut s = "https://up.jottacloud.com/jfs/".to_owned();
-        s.push_str(&self.username);
-        s.push_str("/Jotta/Sync/");
-        s.push_str(path);
-
-        debug!("upload via '{}'", s);
-
-        let auth = self.authorization.clone(); // TODO: can we get rid of cloning? Oo
+        let client = self.clone();
+        let path = path.clone();
 
         data.concat2()
             .map_err(|e| failure::Error::from(e))
             .and_then(move |bytes| {
                 let digest = format!("{:x}", md5::compute(bytes.as_ref()));
-                let date = format!("{}", chrono::Utc::now());
-
-                let mut mpart = MultipartRequest::default();
-                mpart.add_field("cphash", &digest);
-                mpart.add_field("md5", &digest);
-                mpart.add_field("created", &date);
-                mpart.add_field("modified", &date);
-                mpart.add_stream(
-                    "file",
-                    "blupp",
-                    "application/octet-stream",
-                    ByteStream::new(bytes.as_ref()), // a big fat note: this is required, as
-                                                     // MultipartRequest only implements Stream, if it's template argument implements
-                                                     // Stream to; which Bytes doesn't.
-                );
 
-                let request = client::ClientRequest::post(s)
-                    .header(AUTHORIZATION, auth)
-                    .header("X-Jfs-DeviceName", "Jotta")
-                    .header("JSize", bytes.len().to_string())
-                    .header("JMd5", digest.clone())
-                    .content_type(format!(
-                        "multipart/form-data; boundary={}",
-                        mpart.get_boundary()
-                    ))
-                    .body(actix_web::Body::Streaming(Box::new(mpart.from_err())));
-                debug!("Upload request: {:?}", request);
-
-                request
-                    .unwrap()
-                    .send()
-                    .timeout(std::time::Duration::from_secs(600)) // TODO: is there a better way?
-                    .map_err(failure::Error::from)
-                    .and_then(JottaClient::handle_client_response)
+                client
+                    .check_existing(&path, bytes.len(), &digest)
+                    .and_then(move |existing| match existing {
+                        Existing::Identical(file) => {
+                            info!(
+                                "{} is already stored with md5 {}, skipping upload",
+                                path, digest
+                            );
+                            Either::A(ok(Object::File(file)))
+                        }
+                        Existing::Incomplete(offset) => {
+                            info!("Resuming incomplete upload of {} at byte {}", path, offset);
+                            Either::B(Either::A(
+                                client.resume_upload(&path, bytes, digest, offset),
+                            ))
+                        }
+                        Existing::Nothing => Either::B(Either::B(
+                            client.upload_by_hash(&path, bytes.len(), &digest).and_then(
+                                move |known| match known {
+                                    Some(obj) => {
+                                        info!(
+                                            "Jotta knows the content of {} by its md5 sum {}",
+                                            path, digest
+                                        );
+                                        Either::A(ok(obj))
+                                    }
+                                    None => Either::B(client.upload_bytes(&path, bytes, digest, 0)),
+                                },
+                            ),
+                        )),
+                    })
             })
     }
 
-    pub fn download(&self, path: &str) -> impl Future<Item = Bytes, Error = failure::Error> {
-        use actix_web::HttpMessage;
-        use hyper::http::StatusCode;
+    /// Checks, whether the content with the given size and md5 sum is already (partially) stored at path.
+    /// Files in the trash don't count. Errors other than 404 are passed on.
+    fn check_existing(
+        &self,
+        path: &JottaPath,
+        size: usize,
+        digest: &str,
+    ) -> impl Future<Item = Existing, Error = failure::Error> {
+        let digest = digest.to_owned();
+
+        self.query_object(path).then(move |res| {
+            Ok(match res {
+                Err(ref e) if is_not_found(e) => Existing::Nothing,
+                Err(e) => return Err(e),
+                Ok(Object::File(ref f))
+                    if f.deleted.is_none()
+                        && f.is_complete()
+                        && f.size() == size
+                        && f.md5() == Some(digest.as_str()) =>
+                {
+                    Existing::Identical(f.clone())
+                }
+                Ok(Object::File(ref f)) => match resume_offset(f, size, &digest) {
+                    Some(offset) => Existing::Incomplete(offset),
+                    None => Existing::Nothing,
+                },
+                _ => Existing::Nothing,
+            })
+        })
+    }
 
-        let mut full_uri = self.base_url.clone();
-        full_uri.push_str(path);
-        full_uri.push_str("?mode=bin");
+    /// Files in the given folder, which have an incomplete upload
+    pub fn list_incomplete(
+        &self,
+        path: &JottaPath,
+    ) -> impl Future<Item = Vec<File>, Error = failure::Error> {
+        self.list(path).map(|dir| {
+            dir.files
+                .into_iter()
+                .filter(|f| {
+                    f.deleted.is_none()
+                        && f.latest_revision
+                            .as_ref()
+                            .map_or(false, |r| !r.is_complete())
+                })
+                .collect()
+        })
+    }
 
-        debug!("download via '{}'", full_uri);
+    /// Deletes a file, which has never been uploaded completely. Files with
+    /// a complete revision are refused, as Jotta can't delete single revisions.
+    pub fn abandon_incomplete(
+        &self,
+        path: &JottaPath,
+    ) -> impl Future<Item = Object, Error = failure::Error> {
+        use futures::future::Either;
+
+        let client = self.clone();
+        let path = path.clone();
+
+        self.query_object(&path).and_then(move |obj| match obj {
+            Object::File(ref f) if f.current_revision.is_none() => {
+                info!("Abandoning incomplete upload of {}", path);
+                Either::A(client.delete(&path))
+            }
+            Object::File(_) => Either::B(err(err_msg(format!(
+                "{} has a complete revision, refusing to delete it",
+                path
+            )))),
+            Object::Folder(_) => Either::B(err(err_msg(format!("{} is not a file", path)))),
+        })
+    }
 
-        let http_request = client::ClientRequest::get(full_uri)
+    /// Asks Jotta to create the file from content it already has, i.e. without sending the data.
+    /// Returns `None`, if Jotta doesn't know the content (404). Other errors are passed on.
+    fn upload_by_hash(
+        &self,
+        path: &JottaPath,
+        size: usize,
+        digest: &str,
+    ) -> impl Future<Item = Option<Object>, Error = failure::Error> {
+        let mut s = self.upload_url.clone();
+        s.push_str(&path.encoded());
+        s.push_str("?cphash=");
+        s.push_str(digest);
+
+        debug!("upload by hash via '{}'", s);
+
+        let http_request = client::ClientRequest::post(s)
             .header(AUTHORIZATION, self.authorization.as_str())
+            .header("X-Jfs-DeviceName", "Jotta")
+            .header("JSize", size.to_string())
+            .header("JMd5", digest)
             .finish()
-            .unwrap()
-            .send();
-
-        http_request.map_err(failure::Error::from).and_then(|res| {
-            let status_code = res.status();
-            res.body()
-                .into_stream()
-                .concat2()
-                .map_err(failure::Error::from)
-                .and_then(move |body_bytes| {
-                    if status_code == StatusCode::OK {
-                        Ok(body_bytes)
-                    } else {
-                        Err(std::str::from_utf8(&body_bytes)
-                            .map_err(failure::Error::from)
-                            .and_then(parse_xml::<JottaError>)
-                            .map(failure::Error::from)
-                            .unwrap_or_else(|e| e)) // return failure in any case
+            .unwrap();
+
+        let digest = digest.to_owned();
+
+        self.transport
+            .send(http_request, REQUEST_TIMEOUT)
+            .and_then(JottaClient::handle_client_response)
+            .then(move |res| {
+                Ok(match res {
+                    Ok(Object::File(f)) => {
+                        if f.is_complete() && f.md5() == Some(digest.as_str()) {
+                            Some(Object::File(f))
+                        } else {
+                            None
+                        }
+                    }
+                    Ok(Object::Folder(_)) => None,
+                    Err(ref e) if is_not_found(e) => {
+                        debug!("Jotta doesn't know content with md5 {}", digest);
+                        None
                     }
+                    Err(e) => return Err(e),
                 })
-        })
+            })
+    }
+
+    /// Sends the rest of an incomplete upload. Should Jotta not take it,
+    /// i.e. as the stored part doesn't fit, everything is sent again.
+    fn resume_upload(
+        &self,
+        path: &JottaPath,
+        bytes: Bytes,
+        digest: String,
+        offset: usize,
+    ) -> impl Future<Item = Object, Error = failure::Error> {
+        let client = self.clone();
+        let path = path.clone();
+
+        self.upload_bytes(&path, bytes.clone(), digest.clone(), offset)
+            .or_else(move |e| {
+                warn!(
+                    "Couldn't resume upload of {} at byte {}, sending everything: {}",
+                    path, offset, e
+                );
+                client.upload_bytes(&path, bytes, digest, 0)
+            })
+    }
+
+    /// Uploads the data, skipping the first `offset` bytes, which Jotta already has
+    fn upload_bytes(
+        &self,
+        path: &JottaPath,
+        bytes: Bytes,
+        digest: String,
+        offset: usize,
+    ) -> impl Future<Item = Object, Error = failure::Error> {
+        use mpart_async::{ByteStream, MultipartRequest};
+
+        // URL is   https://up.jottacloud.com/jfs/[...]
+
+        let mut s = self.upload_url.clone();
+        s.push_str(&path.encoded());
+
+        debug!("upload via '{}'", s);
+
+        let verify = self.verify_uploads;
+        let display_path = path.to_string();
+        let date = format!("{}", chrono::Utc::now());
+
+        let mut mpart = MultipartRequest::default();
+        mpart.add_field("cphash", &digest);
+        mpart.add_field("md5", &digest);
+        mpart.add_field("created", &date);
+        mpart.add_field("modified", &date);
+        mpart.add_stream(
+            "file",
+            "blupp",
+            "application/octet-stream",
+            ByteStream::new(&bytes[offset..]), // a big fat note: this is required, as
+                                               // MultipartRequest only implements Stream, if it's template argument implements
+                                               // Stream to; which Bytes doesn't.
+        );
+
+        let mut request = client::ClientRequest::post(s);
+        request
+            .header(AUTHORIZATION, self.authorization.as_str())
+            .header("X-Jfs-DeviceName", "Jotta")
+            .header("JSize", bytes.len().to_string())
+            .header("JMd5", digest.clone());
+        if offset > 0 {
+            request.header(
+                "Content-Range",
+                format!("bytes {}-{}/{}", offset, bytes.len() - 1, bytes.len()),
+            );
+        }
+        let request = request
+            .content_type(format!(
+                "multipart/form-data; boundary={}",
+                mpart.get_boundary()
+            ))
+            .body(actix_web::Body::Streaming(Box::new(mpart.from_err())));
+        debug!("Upload request: {:?}", request);
+
+        self.transport
+            .send(request.unwrap(), UPLOAD_TIMEOUT)
+            .and_then(JottaClient::handle_client_response)
+            .and_then(move |obj| {
+                if verify {
+                    JottaClient::verify_upload(&display_path, &digest, obj)
+                } else {
+                    Ok(obj)
+                }
+            })
+    }
+
+    /// Makes sure Jotta stored a complete revision with the md5 sum we sent
+    fn verify_upload(path: &str, digest: &str, obj: Object) -> Result<Object, failure::Error> {
+        let actual = match obj {
+            Object::File(ref f) if f.is_complete() => f.md5().unwrap_or_default().to_owned(),
+            Object::File(_) => "<incomplete>".to_owned(),
+            Object::Folder(_) => return Err(err_msg(format!("{} is not a file", path))),
+        };
+
+        if actual == digest {
+            Ok(obj)
+        } else {
+            Err(IntegrityError {
+                path: path.to_owned(),
+                expected: digest.to_owned(),
+                actual,
+            }
+            .into())
+        }
+    }
+
+    /// Downloads the current revision of a file and checks it against the md5 sum Jotta reports
+    pub fn download(&self, path: &JottaPath) -> impl Future<Item = Bytes, Error = failure::Error> {
+        use hyper::http::StatusCode;
+
+        let mut full_uri = self.url(path);
+        full_uri.push_str("?mode=bin");
+
+        let authorization = self.authorization.clone();
+        let transport = self.transport.clone();
+        let display_path = path.to_string();
+        let path_for_check = display_path.clone();
+
+        self.query_object(path)
+            .and_then(move |obj| match obj {
+                Object::File(ref f) if f.is_complete() => {
+                    ok(f.md5().unwrap_or_default().to_owned())
+                }
+                Object::File(_) => err(IncompleteError {
+                    path: path_for_check,
+                }
+                .into()),
+                Object::Folder(_) => err(err_msg(format!("{} is not a file", path_for_check))),
+            })
+            .and_then(move |expected| {
+                debug!("download via '{}'", full_uri);
+
+                let http_request = client::ClientRequest::get(full_uri)
+                    .header(AUTHORIZATION, authorization)
+                    .finish()
+                    .unwrap();
+
+                transport
+                    .send(http_request, DOWNLOAD_TIMEOUT)
+                    .and_then(move |res| {
+                        if res.status == StatusCode::OK {
+                            let actual = format!("{:x}", md5::compute(res.body.as_ref()));
+                            if actual == expected {
+                                Ok(res.body)
+                            } else {
+                                Err(IntegrityError {
+                                    path: display_path,
+                                    expected,
+                                    actual,
+                                }
+                                .into())
+                            }
+                        } else {
+                            Err(std::str::from_utf8(&res.body)
+                                .map_err(failure::Error::from)
+                                .and_then(parse_xml::<JottaError>)
+                                .map(failure::Error::from)
+                                .unwrap_or_else(|e| e)) // return failure in any case
+                        }
+                    })
+            })
     }
 
-    pub fn mkdir(&self, path: &str) -> impl Future<Item = Object, Error = failure::Error> {
+    pub fn mkdir(&self, path: &JottaPath) -> impl Future<Item = Object, Error = failure::Error> {
         // cf https://github.com/oleid/jottalib/blob/add_restic_server/src/jottalib/JFS.py
 
-        let mut full_uri = self.base_url.clone();
-        full_uri.push_str(path);
+        let mut full_uri = self.url(path);
         full_uri.push_str("?mkDir=true");
 
         debug!("mkdir via '{}'", full_uri);
 
-        client::ClientRequest::post(full_uri)
+        let http_request = client::ClientRequest::post(full_uri)
             .header(AUTHORIZATION, self.authorization.as_str())
             .finish()
-            .unwrap()
-            .send()
-            .map_err(failure::Error::from)
+            .unwrap();
+
+        self.transport
+            .send(http_request, REQUEST_TIMEOUT)
             .and_then(JottaClient::handle_client_response)
     }
 
-    pub fn exists(&self, path: &str) -> impl Future<Item = bool, Error = failure::Error> {
+    /// Moves a file or folder. The target must be in the same mount point.
+    pub fn rename(
+        &self,
+        from: &JottaPath,
+        to: &JottaPath,
+    ) -> impl Future<Item = Object, Error = failure::Error> {
+        debug!("rename of {} to {}", from, to);
+        let authorization = self.authorization.clone();
+        let transport = self.transport.clone();
+        let mut full_uri = self.url(from);
+        // Jotta wants the target relative to the user's root, i.e. including the mount point
+        let target = self.mount_point.concat(to).query_encoded();
+
+        self.query_object(from)
+            .and_then(move |obj| {
+                full_uri.push_str(match obj {
+                    Object::File(_) => "?mv=",
+                    Object::Folder(_) => "?mvDir=",
+                });
+                full_uri.push_str(&target);
+                debug!("rename via '{}'", full_uri);
+
+                ok(full_uri)
+            })
+            .and_then(move |uri| {
+                let http_request = client::ClientRequest::post(uri)
+                    .header(AUTHORIZATION, authorization)
+                    .finish()
+                    .unwrap();
+
+                transport
+                    .send(http_request, REQUEST_TIMEOUT)
+                    .and_then(JottaClient::handle_client_response)
+            })
+    }
+
+    pub fn exists(&self, path: &JottaPath) -> impl Future<Item = bool, Error = failure::Error> {
         debug!("exists '{}'", path);
 
         self.query_object(path)
@@ -253,19 +642,19 @@ impl JottaClient {
                 Object::Folder(_) => ok(true),
             })
             .or_else(|error: failure::Error| {
-                error
-                    .find_root_cause()
-                    .downcast_ref::<JottaError>()
-                    .and_then(|ref e| if e.code == 404 { Some(ok(false)) } else { None })
-                    .unwrap_or(err(error))
+                if is_not_found(&error) {
+                    ok(false)
+                } else {
+                    err(error)
+                }
             })
     }
 
-    pub fn delete(&self, path: &str) -> impl Future<Item = Object, Error = failure::Error> {
+    pub fn delete(&self, path: &JottaPath) -> impl Future<Item = Object, Error = failure::Error> {
         debug!("delete of {}", path);
         let authorization = self.authorization.clone();
-        let mut full_uri = self.base_url.clone();
-        full_uri.push_str(path);
+        let transport = self.transport.clone();
+        let mut full_uri = self.url(path);
 
         self.query_object(path)
             .and_then(move |obj| {
@@ -278,12 +667,13 @@ impl JottaClient {
                 ok(full_uri)
             })
             .and_then(move |uri| {
-                client::ClientRequest::post(uri)
+                let http_request = client::ClientRequest::post(uri)
                     .header(AUTHORIZATION, authorization)
                     .finish()
-                    .unwrap()
-                    .send()
-                    .map_err(failure::Error::from)
+                    .unwrap();
+
+                transport
+                    .send(http_request, REQUEST_TIMEOUT)
                     .and_then(JottaClient::handle_client_response)
             })
     }
@@ -324,7 +714,7 @@ mod test {
         let password = retrieve_password(username);
 
         TestFixture {
-            client: JottaClient::new(username, &password),
+            client: JottaClient::new(username, &password).unwrap(),
         }
     }
 
@@ -349,6 +739,10 @@ mod test {
         };
     }
 
+    fn path(p: &str) -> JottaPath {
+        p.parse().unwrap()
+    }
+
     fn obj_is_folder<E>(obj: Result<Object, E>) -> bool {
         obj.map(|v| match v {
             Object::Folder(_) => true,
@@ -368,7 +762,7 @@ mod test {
     #[test]
     fn test_001_mkdir() {
         run_test!(
-            |fixture: &TestFixture| fixture.client.mkdir("/test"),
+            |fixture: &TestFixture| fixture.client.mkdir(&path("/test")),
             obj_is_folder
         );
     }
@@ -379,7 +773,7 @@ mod test {
             |fixture: &TestFixture| {
                 let data = ok(Bytes::from_static("Hallo Welt".as_bytes())).into_stream();
 
-                fixture.client.upload("/test/blupp.dat", data)
+                fixture.client.upload(&path("/test/blupp.dat"), data)
             },
             obj_is_file
         );
@@ -389,7 +783,7 @@ mod test {
     fn test_021_exists() {
         let is_true = |v: Result<bool, _>| v.unwrap_or(false);
         run_test!(
-            |fixture: &TestFixture| fixture.client.exists("/test/blupp.dat"),
+            |fixture: &TestFixture| fixture.client.exists(&path("/test/blupp.dat")),
             is_true
         );
     }
@@ -397,7 +791,7 @@ mod test {
     #[test]
     fn test_031_download() {
         run_test!(
-            |fixture: &TestFixture| fixture.client.download("/test/blupp.dat"),
+            |fixture: &TestFixture| fixture.client.download(&path("/test/blupp.dat")),
             |v: Result<Bytes, _>| v
                 .map(|x| Bytes::from_static(b"Hallo Welt") == x)
                 .unwrap_or(false)
@@ -411,7 +805,7 @@ mod test {
             f.map(|folder| folder.name == "test").unwrap_or(false)
         };
         run_test!(
-            |fixture: &TestFixture| fixture.client.list("/test"),
+            |fixture: &TestFixture| fixture.client.list(&path("/test")),
             is_folder_test
         );
     }
@@ -419,7 +813,7 @@ mod test {
     #[test]
     fn test_051_delete_file() {
         run_test!(
-            |fixture: &TestFixture| fixture.client.delete("/test/blupp.dat"),
+            |fixture: &TestFixture| fixture.client.delete(&path("/test/blupp.dat")),
             obj_is_file
         );
     }
@@ -427,7 +821,7 @@ mod test {
     #[test]
     fn test_061_delete_folder() {
         run_test!(
-            |fixture: &TestFixture| fixture.client.delete("/test"),
+            |fixture: &TestFixture| fixture.client.delete(&path("/test")),
             obj_is_folder
         );
     }
@@ -2,12 +2,13 @@ use crate::error::{JfsXmlError, JottaError};
 use crate::file::File;
 use crate::folder::Folder;
 use crate::fromxml::FromXml;
+use crate::toxml::ToXml;
 use crate::util::*;
 use quick_xml::events::Event;
 use quick_xml::Reader;
 use std::str::FromStr;
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Object {
     File(File),
     Folder(Folder),
@@ -22,6 +23,15 @@ impl Object {
     }
 }
 
+impl ToXml for Object {
+    fn write_xml(&self, out: &mut String) {
+        match self {
+            Object::File(ref file) => file.write_xml(out),
+            Object::Folder(ref dir) => dir.write_xml(out),
+        }
+    }
+}
+
 impl FromStr for Object {
     type Err = failure::Error;
 
@@ -0,0 +1,231 @@
+use failure::Error;
+use percent_encoding::{
+    define_encode_set, percent_decode, utf8_percent_encode, EncodeSet, PATH_SEGMENT_ENCODE_SET,
+};
+use std::fmt;
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+
+define_encode_set! {
+    /// For paths used as query value, i.e. the target of a move
+    pub QUERY_PATH_SEGMENT_ENCODE_SET = [PATH_SEGMENT_ENCODE_SET] | {'&', '=', '+', ';'}
+}
+
+#[derive(Debug, Fail)]
+pub enum JottaPathError {
+    #[fail(display = "Path {:?} leaves the root folder", path)]
+    EscapesRoot { path: String },
+    #[fail(display = "Invalid path segment {:?}", segment)]
+    InvalidSegment { segment: String },
+    #[fail(display = "Couldn't percent-decode path {:?}", path)]
+    InvalidEncoding { path: String },
+}
+
+/// A validated path below the mount point (i.e. `Jotta/Sync`).
+///
+/// Segments are NFC normalized, `.` and `..` are resolved and
+/// the path is guaranteed not to leave the mount point.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct JottaPath {
+    segments: Vec<String>,
+}
+
+fn check_segment(segment: &str) -> Result<(), Error> {
+    if segment.is_empty() || segment.contains('/') || segment.chars().any(char::is_control) {
+        Err(JottaPathError::InvalidSegment {
+            segment: segment.to_owned(),
+        }
+        .into())
+    } else {
+        Ok(())
+    }
+}
+
+impl JottaPath {
+    pub fn root() -> JottaPath {
+        JottaPath::default()
+    }
+
+    /// Builds a path from plain (not percent-encoded) segments separated by `/`
+    pub fn new(path: &str) -> Result<JottaPath, Error> {
+        JottaPath::from_segments(path, path.split('/').map(|s| s.to_owned()))
+    }
+
+    /// Builds a path from the path part of an URL, i.e. `req.path()`.
+    /// Decoding happens per segment, hence an encoded `/` is refused.
+    pub fn from_url_path(path: &str) -> Result<JottaPath, Error> {
+        let segments = path
+            .split('/')
+            .map(|s| {
+                percent_decode(s.as_bytes())
+                    .decode_utf8()
+                    .map(|s| s.into_owned())
+                    .map_err(|_| JottaPathError::InvalidEncoding {
+                        path: path.to_owned(),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        JottaPath::from_segments(path, segments.into_iter())
+    }
+
+    fn from_segments<I>(path: &str, segments: I) -> Result<JottaPath, Error>
+    where
+        I: Iterator<Item = String>,
+    {
+        let mut result = JottaPath::root();
+
+        for segment in segments {
+            match segment.as_str() {
+                "" | "." => (),
+                ".." => {
+                    if result.segments.pop().is_none() {
+                        return Err(JottaPathError::EscapesRoot {
+                            path: path.to_owned(),
+                        }
+                        .into());
+                    }
+                }
+                s => {
+                    check_segment(s)?;
+                    result.segments.push(s.nfc().collect());
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Appends a single segment, i.e. a file name
+    pub fn join(&self, name: &str) -> Result<JottaPath, Error> {
+        check_segment(name)?;
+        if name == "." || name == ".." {
+            return Err(JottaPathError::InvalidSegment {
+                segment: name.to_owned(),
+            }
+            .into());
+        }
+
+        let mut result = self.clone();
+        result.segments.push(name.nfc().collect());
+        Ok(result)
+    }
+
+    pub fn parent(&self) -> Option<JottaPath> {
+        if self.is_root() {
+            None
+        } else {
+            Some(JottaPath {
+                segments: self.segments[..self.segments.len() - 1].to_vec(),
+            })
+        }
+    }
+
+    pub fn file_name(&self) -> Option<&str> {
+        self.segments.last().map(String::as_str)
+    }
+
+    pub fn segments(&self) -> &[String] {
+        &self.segments
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn starts_with(&self, prefix: &JottaPath) -> bool {
+        self.segments.starts_with(&prefix.segments)
+    }
+
+    /// Appends all segments of another path
+    pub fn concat(&self, other: &JottaPath) -> JottaPath {
+        let mut result = self.clone();
+        result.segments.extend(other.segments.iter().cloned());
+        result
+    }
+
+    fn encoded_with<E: EncodeSet + Copy>(&self, encode_set: E) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            out.push('/');
+            out.extend(utf8_percent_encode(segment, encode_set));
+        }
+        out
+    }
+
+    /// The percent-encoded form, to be appended to the URL of the mount point.
+    /// Empty for the root folder, otherwise starting with `/`.
+    pub fn encoded(&self) -> String {
+        self.encoded_with(PATH_SEGMENT_ENCODE_SET)
+    }
+
+    /// The percent-encoded form for use as value in a query string
+    pub fn query_encoded(&self) -> String {
+        self.encoded_with(QUERY_PATH_SEGMENT_ENCODE_SET)
+    }
+}
+
+impl fmt::Display for JottaPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_root() {
+            f.write_str("/")
+        } else {
+            for segment in &self.segments {
+                write!(f, "/{}", segment)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+impl FromStr for JottaPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        JottaPath::new(s)
+    }
+}
+
+#[test]
+fn test_encoding() {
+    let path = JottaPath::new("/test/a b#c?d/ünïcödé").unwrap();
+
+    assert_eq!(
+        path.encoded(),
+        "/test/a%20b%23c%3Fd/%C3%BCn%C3%AFc%C3%B6d%C3%A9"
+    );
+    assert_eq!(JottaPath::from_url_path(&path.encoded()).unwrap(), path);
+    assert_eq!(path.to_string(), "/test/a b#c?d/ünïcödé");
+}
+
+#[test]
+fn test_query_encoding() {
+    let path = JottaPath::new("/a&b=c+d/e f").unwrap();
+
+    assert_eq!(path.query_encoded(), "/a%26b%3Dc%2Bd/e%20f");
+    assert_eq!(path.encoded(), "/a&b=c+d/e%20f");
+}
+
+#[test]
+fn test_normalization() {
+    // "é" as a single code point (NFC) and as e + combining accent (NFD)
+    assert_eq!(
+        JottaPath::new("/caf\u{e9}").unwrap(),
+        JottaPath::new("/cafe\u{301}").unwrap()
+    );
+    assert_eq!(
+        JottaPath::new("//a/./b/../c/").unwrap(),
+        JottaPath::new("/a/c").unwrap()
+    );
+    assert_eq!(JottaPath::new("/").unwrap().encoded(), "");
+}
+
+#[test]
+fn test_invalid() {
+    assert!(JottaPath::new("/a/../..").is_err());
+    assert!(JottaPath::from_url_path("/a/..%2F..").is_err());
+    assert!(JottaPath::from_url_path("/a%2Fb").is_err());
+    assert!(JottaPath::from_url_path("/a%00b").is_err());
+    assert!(JottaPath::root().join("..").is_err());
+    assert!(JottaPath::root().join("a/b").is_err());
+}
@@ -0,0 +1,126 @@
+use crate::error::JfsXmlError;
+use crate::fromxml::FromXml;
+use crate::toxml::*;
+use crate::util::*;
+use failure::Error;
+use mime::Mime;
+use quick_xml::events::{attributes::Attributes, Event};
+use quick_xml::Reader;
+use std::collections::BTreeMap;
+use std::io::BufRead;
+
+/// One revision of a file, as found in `currentRevision`, `latestRevision`
+/// and `revisions` of a file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Revision {
+    pub number: usize,
+    pub state: TransferState,
+    pub created: TimeStamp,
+    pub modified: TimeStamp,
+    #[serde(with = "serde_mime")]
+    pub mime: Mime,
+    /// For incomplete revisions, the bytes Jotta has so far. Only reported
+    /// when asking for the file itself, not in folder listings, hence 0 then.
+    pub size: usize,
+    pub md5: String,
+    pub updated: TimeStamp,
+    /// Elements we don't know (yet), by tag name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
+}
+
+impl Revision {
+    pub fn is_complete(&self) -> bool {
+        self.state == TransferState::Completed
+    }
+
+    /// Writes this revision with the given tag, as the same content is
+    /// used for `currentRevision`, `latestRevision` and `revision`.
+    pub fn write_xml_as(&self, out: &mut String, tag: &str) {
+        write_start(out, tag, &[]);
+        write_element(out, "number", &self.number);
+        write_element(out, "state", &self.state);
+        write_timestamp(out, "created", &self.created);
+        write_timestamp(out, "modified", &self.modified);
+        write_element(out, "mime", &self.mime);
+        if self.is_complete() || self.size > 0 {
+            write_element(out, "size", &self.size);
+        }
+        write_element(out, "md5", &self.md5);
+        write_timestamp(out, "updated", &self.updated);
+        write_extra(out, &self.extra);
+        write_end(out, tag);
+    }
+}
+
+impl FromXml for Revision {
+    const TAG: &'static str = "revision";
+
+    fn from_xml<R: BufRead>(reader: &mut Reader<R>, _attrs: Attributes) -> Result<Self, Error> {
+        use failure::err_msg;
+        use std::str::from_utf8;
+
+        let mut number = None;
+        let mut state = None;
+        let mut created = None;
+        let mut modified = None;
+        let mut mime_type = None;
+        let mut size = None;
+        let mut md5 = None;
+        let mut updated = None;
+        let mut extra = BTreeMap::new();
+        let mut buf = Vec::new();
+
+        loop {
+            match reader.read_event(&mut buf)? {
+                Event::Start(element) => {
+                    debug!("New element: {}", from_utf8(element.name())?);
+                    match element.name() {
+                        b"number" => number = element_usize(reader)?,
+                        b"state" => state = element_transfer_state(reader)?,
+                        b"created" => created = element_timestamp(reader)?,
+                        b"modified" => modified = element_timestamp(reader)?,
+                        b"mime" => mime_type = element_mime(reader)?,
+                        b"size" => size = element_usize(reader)?,
+                        b"md5" => md5 = element_text(reader)?,
+                        b"updated" => updated = element_timestamp(reader)?,
+                        n => element_unknown(reader, n, &mut extra)?,
+                    }
+                }
+                Event::End(_) => {
+                    // all children are read completely, hence this is our own end tag
+                    debug!("Closing element revision, we're done here.");
+                    break;
+                }
+                Event::Empty(element) => match element.name() {
+                    // known, but without content
+                    b"number" | b"state" | b"created" | b"modified" | b"mime" | b"size"
+                    | b"md5" | b"updated" => (),
+                    n => element_unknown_empty(n, &mut extra)?,
+                },
+                Event::Eof => return Err(JfsXmlError::UnexpectedEndOfFile.into()),
+                _ => {}
+            }
+
+            buf.clear();
+        }
+
+        Ok(Revision {
+            number: number.ok_or(err_msg("Couldn't parse number of revision"))?,
+            state: state.ok_or(err_msg("Couldn't parse state of revision"))?,
+            created: created.ok_or(err_msg("Couldn't parse creation time of revision"))?,
+            modified: modified.ok_or(err_msg("Couldn't parse modification time of revision"))?,
+            mime: mime_type.unwrap_or(mime::APPLICATION_OCTET_STREAM),
+            size: size.unwrap_or(0),
+            md5: md5.ok_or(err_msg("Couldn't parse md5 sum of revision"))?,
+            updated: updated.ok_or(err_msg("Couldn't parse update time of revision"))?,
+            extra,
+        })
+    }
+}
+
+impl ToXml for Revision {
+    fn write_xml(&self, out: &mut String) {
+        self.write_xml_as(out, Self::TAG);
+    }
+}
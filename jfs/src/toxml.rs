@@ -0,0 +1,86 @@
+// Writes our types back as XML, the way Jotta sends them. This allows
+// i.e. a mock server to hand out the same documents as JottaCloud.
+use crate::util::{format_jotta_timestamp, TimeStamp};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#;
+
+pub trait ToXml {
+    fn write_xml(&self, out: &mut String);
+
+    fn to_xml(&self) -> String {
+        let mut out = String::from(XML_DECLARATION);
+        out.push('\n');
+        self.write_xml(&mut out);
+        out
+    }
+}
+
+pub fn escape(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Writes `<tag attr="value" ...>`, leaving out attributes without value
+pub fn write_start(out: &mut String, tag: &str, attrs: &[(&str, Option<String>)]) {
+    out.push('<');
+    out.push_str(tag);
+    for (key, value) in attrs {
+        if let Some(value) = value {
+            out.push_str(&format!(r#" {}="{}""#, key, escape(value)));
+        }
+    }
+    out.push('>');
+}
+
+pub fn write_end(out: &mut String, tag: &str) {
+    out.push_str("</");
+    out.push_str(tag);
+    out.push('>');
+}
+
+pub fn write_element<T: Display>(out: &mut String, tag: &str, value: &T) {
+    write_start(out, tag, &[]);
+    out.push_str(&escape(&value.to_string()));
+    write_end(out, tag);
+}
+
+pub fn write_optional_element<T: Display>(out: &mut String, tag: &str, value: &Option<T>) {
+    if let Some(value) = value {
+        write_element(out, tag, value);
+    }
+}
+
+pub fn write_timestamp(out: &mut String, tag: &str, timestamp: &TimeStamp) {
+    write_element(out, tag, &format_jotta_timestamp(timestamp));
+}
+
+pub fn write_list<T: ToXml>(out: &mut String, tag: &str, items: &[T]) {
+    write_start(out, tag, &[]);
+    for item in items {
+        item.write_xml(out);
+    }
+    write_end(out, tag);
+}
+
+pub fn write_extra(out: &mut String, extra: &BTreeMap<String, String>) {
+    for (tag, value) in extra {
+        write_element(out, tag, value);
+    }
+}
+
+#[test]
+fn test_escape() {
+    assert_eq!(escape(r#"a<b>&"c'"#), "a&lt;b&gt;&amp;&quot;c&apos;");
+}
@@ -0,0 +1,281 @@
+use crate::error::JfsXmlError;
+use crate::fromxml::*;
+use crate::toxml::*;
+use crate::util::*;
+use failure::Error;
+use quick_xml::events::{attributes::Attributes, Event};
+use quick_xml::Reader;
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::str::FromStr;
+
+/// The account information found at https://www.jottacloud.com/jfs/<username>
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct User {
+    pub username: String,
+    pub request_time: Option<TimeStamp>,
+    pub account_type: String,
+    pub locked: bool,
+    /// In bytes, -1 means unlimited
+    pub capacity: i64,
+    pub max_devices: i64,
+    pub max_mobile_devices: i64,
+    /// In bytes
+    pub usage: i64,
+    pub read_locked: bool,
+    pub write_locked: bool,
+    pub quota_write_locked: bool,
+    pub enable_sync: bool,
+    pub enable_foldershare: bool,
+    #[serde(default)]
+    pub devices: Vec<Device>,
+    /// Elements we don't know (yet), by tag name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct Device {
+    pub name: String,
+    pub display_name: String,
+    pub device_type: String,
+    pub sid: String,
+    pub size: i64,
+    pub modified: Option<TimeStamp>,
+    /// Elements we don't know (yet), by tag name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
+}
+
+impl User {
+    /// Free space in bytes, `None` if the account is unlimited
+    pub fn remaining(&self) -> Option<i64> {
+        if self.capacity < 0 {
+            None
+        } else {
+            Some((self.capacity - self.usage).max(0))
+        }
+    }
+}
+
+impl FromXml for User {
+    const TAG: &'static str = "user";
+
+    fn from_xml<R: BufRead>(reader: &mut Reader<R>, attrs: Attributes) -> Result<Self, Error> {
+        use std::str::from_utf8;
+
+        let mut user = User::default();
+        let mut buf = Vec::new();
+
+        for attr in attrs {
+            let a = attr?;
+            match a.key {
+                b"time" => user.request_time = Some(parse_jotta_timestamp(from_utf8(&a.value)?)?),
+                b"host" => (), // ignored
+                _ => debug!("Unhandled attribute {:?}", from_utf8(&a.value)),
+            }
+        }
+        loop {
+            match reader.read_event(&mut buf)? {
+                Event::Start(element) => {
+                    debug!("New element: {}", from_utf8(element.name())?);
+                    match element.name() {
+                        b"username" => user.username = element_text(reader)?.unwrap_or_default(),
+                        b"account-type" => {
+                            user.account_type = element_text(reader)?.unwrap_or_default()
+                        }
+                        b"locked" => user.locked = element_bool(reader)?.unwrap_or_default(),
+                        b"capacity" => user.capacity = element_i64(reader)?.unwrap_or(-1),
+                        b"max-devices" => user.max_devices = element_i64(reader)?.unwrap_or(-1),
+                        b"max-mobile-devices" => {
+                            user.max_mobile_devices = element_i64(reader)?.unwrap_or(-1)
+                        }
+                        b"usage" => user.usage = element_i64(reader)?.unwrap_or_default(),
+                        b"read-locked" => {
+                            user.read_locked = element_bool(reader)?.unwrap_or_default()
+                        }
+                        b"write-locked" => {
+                            user.write_locked = element_bool(reader)?.unwrap_or_default()
+                        }
+                        b"quota-write-locked" => {
+                            user.quota_write_locked = element_bool(reader)?.unwrap_or_default()
+                        }
+                        b"enable-sync" => {
+                            user.enable_sync = element_bool(reader)?.unwrap_or_default()
+                        }
+                        b"enable-foldershare" => {
+                            user.enable_foldershare = element_bool(reader)?.unwrap_or_default()
+                        }
+                        b"devices" => user.devices = parse_list(reader, b"devices")?,
+                        n => element_unknown(reader, n, &mut user.extra)?,
+                    }
+                }
+                Event::End(element) => {
+                    if element.name() != b"user" {
+                        debug!("Closing element {}, continue", from_utf8(element.name())?);
+                        continue;
+                    } else {
+                        debug!("Closing element user, we're done here.");
+                        break;
+                    }
+                }
+                Event::Empty(element) => match element.name() {
+                    // known, but without content
+                    b"username"
+                    | b"account-type"
+                    | b"locked"
+                    | b"capacity"
+                    | b"max-devices"
+                    | b"max-mobile-devices"
+                    | b"usage"
+                    | b"read-locked"
+                    | b"write-locked"
+                    | b"quota-write-locked"
+                    | b"enable-sync"
+                    | b"enable-foldershare"
+                    | b"devices" => (),
+                    n => element_unknown_empty(n, &mut user.extra)?,
+                },
+                Event::Eof => return Err(JfsXmlError::UnexpectedEndOfFile.into()),
+                _ => {}
+            }
+
+            buf.clear();
+        }
+
+        Ok(user)
+    }
+}
+
+impl FromXml for Device {
+    const TAG: &'static str = "device";
+
+    fn from_xml<R: BufRead>(reader: &mut Reader<R>, _attrs: Attributes) -> Result<Self, Error> {
+        use std::str::from_utf8;
+
+        let mut device = Device::default();
+        let mut buf = Vec::new();
+
+        loop {
+            match reader.read_event(&mut buf)? {
+                Event::Start(element) => {
+                    debug!("New element: {}", from_utf8(element.name())?);
+                    match element.name() {
+                        b"name" => device.name = element_text(reader)?.unwrap_or_default(),
+                        b"display_name" => {
+                            device.display_name = element_text(reader)?.unwrap_or_default()
+                        }
+                        b"type" => device.device_type = element_text(reader)?.unwrap_or_default(),
+                        b"sid" => device.sid = element_text(reader)?.unwrap_or_default(),
+                        b"size" => device.size = element_i64(reader)?.unwrap_or_default(),
+                        b"modified" => device.modified = element_timestamp(reader)?,
+                        n => element_unknown(reader, n, &mut device.extra)?,
+                    }
+                }
+                Event::End(_) => {
+                    // all children are read completely, hence this is our own end tag
+                    debug!("Closing element device, we're done here.");
+                    break;
+                }
+                Event::Empty(element) => match element.name() {
+                    // known, but without content
+                    b"name" | b"display_name" | b"type" | b"sid" | b"size" | b"modified" => (),
+                    n => element_unknown_empty(n, &mut device.extra)?,
+                },
+                Event::Eof => return Err(JfsXmlError::UnexpectedEndOfFile.into()),
+                _ => {}
+            }
+
+            buf.clear();
+        }
+
+        Ok(device)
+    }
+}
+
+impl ToXml for User {
+    fn write_xml(&self, out: &mut String) {
+        write_start(
+            out,
+            Self::TAG,
+            &[(
+                "time",
+                self.request_time.as_ref().map(format_jotta_timestamp),
+            )],
+        );
+        write_element(out, "username", &self.username);
+        write_element(out, "account-type", &self.account_type);
+        write_element(out, "locked", &self.locked);
+        write_element(out, "capacity", &self.capacity);
+        write_element(out, "max-devices", &self.max_devices);
+        write_element(out, "max-mobile-devices", &self.max_mobile_devices);
+        write_element(out, "usage", &self.usage);
+        write_element(out, "read-locked", &self.read_locked);
+        write_element(out, "write-locked", &self.write_locked);
+        write_element(out, "quota-write-locked", &self.quota_write_locked);
+        write_element(out, "enable-sync", &self.enable_sync);
+        write_element(out, "enable-foldershare", &self.enable_foldershare);
+        write_list(out, "devices", &self.devices);
+        write_extra(out, &self.extra);
+        write_end(out, Self::TAG);
+    }
+}
+
+impl ToXml for Device {
+    fn write_xml(&self, out: &mut String) {
+        write_start(out, Self::TAG, &[]);
+        write_element(out, "name", &self.name);
+        write_element(out, "display_name", &self.display_name);
+        write_element(out, "type", &self.device_type);
+        write_element(out, "sid", &self.sid);
+        write_element(out, "size", &self.size);
+        if let Some(ref modified) = self.modified {
+            write_timestamp(out, "modified", modified);
+        }
+        write_extra(out, &self.extra);
+        write_end(out, Self::TAG);
+    }
+}
+
+impl_from_str!(User);
+
+#[test]
+fn test_from_str() {
+    crate::fromxml::set_strict_parsing(true);
+
+    let user = r#"
+<user time="2019-01-20-T10:03:54Z" host="dn-093">
+  <username>oleidinger</username>
+  <account-type>unlimited</account-type>
+  <locked>false</locked>
+  <capacity>-1</capacity>
+  <max-devices>-1</max-devices>
+  <max-mobile-devices>-1</max-mobile-devices>
+  <usage>2039672393</usage>
+  <read-locked>false</read-locked>
+  <write-locked>false</write-locked>
+  <quota-write-locked>false</quota-write-locked>
+  <enable-sync>true</enable-sync>
+  <enable-foldershare>true</enable-foldershare>
+  <devices>
+    <device>
+      <name xml:space="preserve">Jotta</name>
+      <display_name xml:space="preserve">Jotta</display_name>
+      <type>JOTTA</type>
+      <sid>ee93a510-907a-4d7c-bbb9-59df78945e2b</sid>
+      <size>58428516</size>
+      <modified>2019-01-20-T10:01:03Z</modified>
+    </device>
+  </devices>
+</user>"#
+        .parse::<User>()
+        .unwrap();
+
+    assert_eq!(user.usage, 2039672393);
+    assert_eq!(user.remaining(), None);
+    assert_eq!(user.devices.len(), 1);
+
+    let again = user.to_xml().parse::<User>().unwrap();
+    assert_eq!(again.devices[0].sid, "ee93a510-907a-4d7c-bbb9-59df78945e2b");
+    assert!(again.enable_sync);
+}
@@ -1,22 +1,33 @@
 use crate::error::JfsXmlError;
-use crate::fromxml::FromXml;
+use crate::fromxml::{strict_parsing, FromXml};
 use chrono::ParseError as ChronoParseError;
 use chrono::{format, format::Item, DateTime, Utc};
 use failure::Error;
 use mime::Mime;
 use quick_xml::events::Event;
 use quick_xml::Reader;
+use std::collections::{BTreeMap, HashSet};
 use std::io::BufRead;
 use std::str::FromStr;
+use std::sync::Mutex;
 
 pub type TimeStamp = DateTime<Utc>;
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum TransferState {
     Incomplete,
     Completed,
 }
 
+impl std::fmt::Display for TransferState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            TransferState::Completed => "COMPLETED",
+            TransferState::Incomplete => "INCOMPLETE",
+        })
+    }
+}
+
 impl FromStr for TransferState {
     type Err = Error;
 
@@ -77,6 +88,28 @@ pub fn parse_jotta_timestamp(input: &str) -> Result<TimeStamp, ChronoParseError>
     parsed.to_datetime().map(|s| s.with_timezone(&Utc))
 }
 
+pub fn format_jotta_timestamp(timestamp: &TimeStamp) -> String {
+    timestamp.format("%Y-%m-%d-T%H:%M:%SZ").to_string()
+}
+
+/// (De)serializes a `Mime` as plain string, as mime doesn't support serde itself.
+pub mod serde_mime {
+    use mime::Mime;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(mime: &Mime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(mime.as_ref())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Mime, D::Error> {
+        use serde::de::Error;
+
+        String::deserialize(deserializer)?
+            .parse::<Mime>()
+            .map_err(D::Error::custom)
+    }
+}
+
 // The following parser code is based on a utils.rs from rss crate,
 // which has the following copyright:
 //
@@ -125,6 +158,14 @@ pub fn element_usize<R: BufRead>(reader: &mut Reader<R>) -> Result<Option<usize>
     element_generic(reader, |v| Ok(v.parse::<usize>()?))
 }
 
+pub fn element_i64<R: BufRead>(reader: &mut Reader<R>) -> Result<Option<i64>, Error> {
+    element_generic(reader, |v| Ok(v.parse::<i64>()?))
+}
+
+pub fn element_bool<R: BufRead>(reader: &mut Reader<R>) -> Result<Option<bool>, Error> {
+    element_generic(reader, |v| Ok(v.parse::<bool>()?))
+}
+
 pub fn element_timestamp<R: BufRead>(reader: &mut Reader<R>) -> Result<Option<TimeStamp>, Error> {
     element_generic(reader, |v| Ok(parse_jotta_timestamp(v)?))
 }
@@ -139,6 +180,53 @@ pub fn element_transfer_state<R: BufRead>(
     element_generic(reader, |v| Ok(v.parse::<TransferState>()?))
 }
 
+lazy_static! {
+    static ref WARNED_TAGS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// Handles an element the parser doesn't know. In strict mode, this is an error,
+/// otherwise its text is stored in `extra` and a warning is logged once per tag.
+pub fn element_unknown<R: BufRead>(
+    reader: &mut Reader<R>,
+    tag: &[u8],
+    extra: &mut BTreeMap<String, String>,
+) -> Result<(), Error> {
+    let tag = accept_unknown(tag)?;
+    let text = element_text(reader)?.unwrap_or_default();
+    extra.insert(tag, text);
+
+    Ok(())
+}
+
+/// Like `element_unknown` for an empty element (`<tag/>`), which readers
+/// report as such, unless they expand empty elements
+pub fn element_unknown_empty(
+    tag: &[u8],
+    extra: &mut BTreeMap<String, String>,
+) -> Result<(), Error> {
+    let tag = accept_unknown(tag)?;
+    extra.insert(tag, String::new());
+
+    Ok(())
+}
+
+/// The name of an unknown tag, unless parsing is strict
+fn accept_unknown(tag: &[u8]) -> Result<String, Error> {
+    use std::str::from_utf8;
+
+    let tag = from_utf8(tag)?.to_owned();
+
+    if strict_parsing() {
+        return Err(JfsXmlError::UnexpectedTag { tag }.into());
+    }
+
+    if WARNED_TAGS.lock().unwrap().insert(tag.clone()) {
+        warn!("Skipping unknown tag <{}> in Jotta's answer", tag);
+    }
+
+    Ok(tag)
+}
+
 pub fn parse_list<R: BufRead, E: FromXml>(
     reader: &mut Reader<R>,
     tag_name: &[u8],
@@ -173,6 +261,16 @@ pub fn parse_list<R: BufRead, E: FromXml>(
     Ok(vec)
 }
 
+#[test]
+pub fn test_timestamp_roundtrip() {
+    let input = "2018-05-19-T00:18:37Z";
+
+    assert_eq!(
+        format_jotta_timestamp(&parse_jotta_timestamp(input).unwrap()),
+        input
+    )
+}
+
 #[test]
 pub fn test_timestamp_format() {
     use chrono::prelude::*;
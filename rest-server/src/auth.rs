@@ -0,0 +1,171 @@
+//! HTTP Basic authentication of restic clients against an htpasswd file
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use actix_web::http::header;
+use actix_web::middleware::{Middleware, Started};
+use actix_web::{HttpRequest, HttpResponse, Result};
+use failure::{err_msg, Error};
+
+use jfs::JottaPath;
+
+use super::AppState;
+
+/// The user a request was authenticated as, stored in the request's extensions
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUser(pub String);
+
+/// Username and password of a Basic `Authorization` header
+pub fn basic_credentials<S>(req: &HttpRequest<S>) -> Option<(String, String)> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    if !value.starts_with("Basic ") {
+        return None;
+    }
+
+    let decoded = base64::decode(value["Basic ".len()..].trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let mut parts = decoded.splitn(2, ':');
+
+    Some((parts.next()?.to_owned(), parts.next()?.to_owned()))
+}
+
+/// The users of an htpasswd file, supporting bcrypt (`$2y$`) and SHA-1 (`{SHA}`) hashes
+pub struct Htpasswd {
+    users: HashMap<String, String>,
+    /// bcrypt is expensive and restic sends plenty of requests, hence remember
+    /// successful checks (user and SHA-256 of the password)
+    verified: Mutex<HashSet<(String, String)>>,
+}
+
+impl Htpasswd {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Htpasswd, Error> {
+        Htpasswd::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(content: &str) -> Result<Htpasswd, Error> {
+        let mut users = HashMap::new();
+
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(user), Some(hash)) if !user.is_empty() => {
+                    users.insert(user.to_owned(), hash.to_owned());
+                }
+                _ => return Err(err_msg(format!("Invalid htpasswd line {:?}", line))),
+            }
+        }
+
+        Ok(Htpasswd {
+            users,
+            verified: Mutex::new(HashSet::new()),
+        })
+    }
+
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        let hash = match self.users.get(user) {
+            Some(hash) => hash,
+            None => return false,
+        };
+
+        let key = (
+            user.to_owned(),
+            crate::restic::sha256_hex(password.as_bytes()),
+        );
+        if self.verified.lock().unwrap().contains(&key) {
+            return true;
+        }
+
+        let valid = if hash.starts_with("{SHA}") {
+            use subtle::ConstantTimeEq;
+
+            let digest = sha1::Sha1::from(password).digest().bytes();
+            let expected = hash["{SHA}".len()..].as_bytes();
+            base64::encode(&digest).as_bytes().ct_eq(expected).into()
+        } else if hash.starts_with("$2") {
+            bcrypt::verify(password, hash).unwrap_or(false)
+        } else {
+            warn!("Unsupported password hash for user {}", user);
+            false
+        };
+
+        if valid {
+            self.verified.lock().unwrap().insert(key);
+        }
+        valid
+    }
+}
+
+/// Refuses requests without valid credentials. With private repositories,
+/// users are confined to the repository named like themselves, which also
+/// rules out the reports below `/.jotta-restic/`, as they cover all repositories.
+pub struct BasicAuth {
+    pub htpasswd: Arc<Htpasswd>,
+    pub private_repos: bool,
+}
+
+/// Asks for credentials, also used with pass-through authentication
+pub(crate) fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .header(header::WWW_AUTHENTICATE, "Basic realm=\"jotta-restic\"")
+        .finish()
+}
+
+impl Middleware<AppState> for BasicAuth {
+    fn start(&self, req: &HttpRequest<AppState>) -> Result<Started> {
+        let (user, password) = match basic_credentials(req) {
+            Some(credentials) => credentials,
+            None => return Ok(Started::Response(unauthorized())),
+        };
+
+        if !self.htpasswd.verify(&user, &password) {
+            warn!("Authentication failed for user {:?}", user);
+            return Ok(Started::Response(unauthorized()));
+        }
+
+        if self.private_repos {
+            let repo = JottaPath::from_url_path(req.path())
+                .ok()
+                .and_then(|path| path.segments().first().cloned());
+
+            if repo.as_ref() != Some(&user) {
+                warn!("User {} may not access {}", user, req.path());
+                return Ok(Started::Response(unauthorized()));
+            }
+        }
+
+        req.extensions_mut().insert(AuthenticatedUser(user));
+        Ok(Started::Done)
+    }
+}
+
+#[test]
+fn test_htpasswd() {
+    let htpasswd = Htpasswd::parse(
+        "# comment\n\
+         alice:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n",
+    )
+    .unwrap();
+
+    assert!(htpasswd.verify("alice", "password"));
+    assert!(htpasswd.verify("alice", "password"));
+    assert!(!htpasswd.verify("alice", "Password"));
+    assert!(!htpasswd.verify("carol", "password"));
+    assert!(Htpasswd::parse("no colon here").is_err());
+}
+
+#[test]
+fn test_htpasswd_bcrypt() {
+    let htpasswd =
+        Htpasswd::parse("bob:$2y$05$Yk7ybECrakTxbEjhJVPxb.2yVvo5optWCWtSjFblmsIDXNs5sDVOm\n")
+            .unwrap();
+
+    assert!(htpasswd.verify("bob", "password"));
+    assert!(!htpasswd.verify("bob", "Password"));
+    assert!(!htpasswd.verify("alice", "password"));
+}
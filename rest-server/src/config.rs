@@ -0,0 +1,300 @@
+use clap::{App, Arg, ArgMatches};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::tls::TlsConfig;
+use jfs::JottaPath;
+
+/// Settings of the server, taken from the command line
+#[derive(Debug, Default)]
+pub struct Config {
+    /// All repositories are kept below this folder
+    pub base: JottaPath,
+    /// Serve a single connection over stdin/stdout
+    pub stdio: bool,
+    /// The repository in stdio mode, as the URLs lack it there
+    pub remote: Option<JottaPath>,
+    /// Address to listen on, if any
+    pub listen: Option<String>,
+    /// Unix socket to listen on
+    pub unix_socket: Option<PathBuf>,
+    /// Permissions of the Unix socket
+    pub unix_socket_mode: u32,
+    /// Authenticate restic clients against this htpasswd file
+    pub htpasswd: Option<PathBuf>,
+    /// Confine users to the repository named like themselves
+    pub private_repos: bool,
+    /// Serve HTTPS instead of plain HTTP
+    pub tls: Option<TlsConfig>,
+    /// Use the client's HTTP Basic credentials as Jotta credentials
+    pub pass_through_auth: bool,
+    /// Delete files, which never finished uploading, after this time
+    pub incomplete_grace: Option<Duration>,
+    /// Re-verify all stored objects, pausing this long between two passes
+    pub scrub_interval: Option<Duration>,
+    /// Bandwidth the scrubber may use, in bytes per second
+    pub scrub_rate: u64,
+    /// How often to fetch the remaining quota of the account
+    pub quota_interval: Duration,
+    /// Usage levels in percent of the quota, which are logged as warning
+    pub quota_warn: Vec<u8>,
+    /// Refuse deletes (except for locks) in all repositories; overwrites are refused anyway
+    pub append_only: bool,
+    /// Refuse deletes (except for locks) in these repositories
+    pub append_only_repos: Vec<String>,
+    /// Refuse everything but reading in all repositories
+    pub read_only: bool,
+    /// Refuse everything but reading in these repositories
+    pub read_only_repos: Vec<String>,
+    /// Still allow to create and remove locks in read-only repositories, i.e. for `restic restore`
+    pub read_only_allow_locks: bool,
+}
+
+fn seconds(matches: &ArgMatches, name: &str) -> Option<Duration> {
+    if matches.is_present(name) {
+        Some(Duration::from_secs(
+            value_t!(matches, name, u64).unwrap_or_else(|e| e.exit()),
+        ))
+    } else {
+        None
+    }
+}
+
+fn repos(matches: &ArgMatches, name: &str) -> Vec<String> {
+    matches
+        .values_of(name)
+        .map(|repos| repos.map(|r| r.trim_matches('/').to_owned()).collect())
+        .unwrap_or_default()
+}
+
+/// restic's rclone backend runs `<program> serve restic --stdio --b2-hard-delete <remote>`,
+/// hence accept (and ignore) the rclone specific parts
+fn rclone_compatible_args() -> Vec<OsString> {
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+
+    if args.len() >= 3 && args[1] == "serve" && args[2] == "restic" {
+        args.drain(1..3);
+    }
+    args.retain(|arg| arg != "--b2-hard-delete");
+
+    args
+}
+
+fn number(matches: &ArgMatches, name: &str, default: u64) -> u64 {
+    if matches.is_present(name) {
+        value_t!(matches, name, u64).unwrap_or_else(|e| e.exit())
+    } else {
+        default
+    }
+}
+
+impl Config {
+    pub fn is_append_only(&self, repo: &str) -> bool {
+        self.append_only || self.append_only_repos.iter().any(|r| r == repo)
+    }
+
+    pub fn is_read_only(&self, repo: &str) -> bool {
+        self.read_only || self.read_only_repos.iter().any(|r| r == repo)
+    }
+
+    pub fn from_args() -> Config {
+        let matches = App::new("jotta-restic")
+            .version(crate_version!())
+            .about("A proxy/translator to connect restic to JottaCloud")
+            .arg(
+                Arg::with_name("base")
+                    .long("base")
+                    .value_name("FOLDER")
+                    .help("Keep all repositories below this folder of Jotta/Sync, i.e. /Backups/restic"),
+            )
+            .arg(
+                Arg::with_name("stdio")
+                    .long("stdio")
+                    .conflicts_with_all(&["listen", "unix-socket", "tls-cert"])
+                    .help("Serve restic over stdin/stdout, i.e. as rclone.program of restic's rclone backend"),
+            )
+            .arg(
+                Arg::with_name("remote")
+                    .value_name("REMOTE")
+                    .index(1)
+                    .requires("stdio")
+                    .help("The repository to serve in stdio mode"),
+            )
+            .arg(
+                Arg::with_name("listen")
+                    .long("listen")
+                    .value_name("ADDRESS")
+                    .help("Address to listen on [default: 127.0.0.1:8080, unless other sockets are used]"),
+            )
+            .arg(
+                Arg::with_name("unix-socket")
+                    .long("unix-socket")
+                    .value_name("PATH")
+                    .help("Listen on a Unix domain socket (plain HTTP)"),
+            )
+            .arg(
+                Arg::with_name("unix-socket-mode")
+                    .long("unix-socket-mode")
+                    .value_name("OCTAL")
+                    .requires("unix-socket")
+                    .help("Permissions of the Unix socket [default: 600]"),
+            )
+            .arg(
+                Arg::with_name("htpasswd")
+                    .long("htpasswd")
+                    .value_name("FILE")
+                    .help("Require HTTP Basic authentication against this htpasswd file (bcrypt or SHA)"),
+            )
+            .arg(
+                Arg::with_name("private-repos")
+                    .long("private-repos")
+                    .requires("htpasswd")
+                    .help("Users may only access the repository named like themselves, i.e. /<user>/"),
+            )
+            .arg(
+                Arg::with_name("tls-cert")
+                    .long("tls-cert")
+                    .value_name("FILE")
+                    .requires("tls-key")
+                    .help("Serve HTTPS with this PEM encoded certificate (chain), reloaded on SIGHUP"),
+            )
+            .arg(
+                Arg::with_name("tls-key")
+                    .long("tls-key")
+                    .value_name("FILE")
+                    .requires("tls-cert")
+                    .help("PEM encoded private key for --tls-cert"),
+            )
+            .arg(
+                Arg::with_name("tls-client-ca")
+                    .long("tls-client-ca")
+                    .value_name("FILE")
+                    .requires("tls-cert")
+                    .help("Require client certificates signed by one of the CAs in this PEM file"),
+            )
+            .arg(
+                Arg::with_name("pass-through-auth")
+                    .long("pass-through-auth")
+                    .conflicts_with_all(&["htpasswd", "scrub-interval"])
+                    .help("Use the credentials restic sends as Jotta credentials, instead of a fixed account"),
+            )
+            .arg(
+                Arg::with_name("incomplete-grace")
+                    .long("incomplete-grace")
+                    .value_name("SECONDS")
+                    .help("Delete files, whose upload never completed, after this many seconds, checking as often in the background"),
+            )
+            .arg(
+                Arg::with_name("scrub-interval")
+                    .long("scrub-interval")
+                    .value_name("SECONDS")
+                    .help("Re-verify all stored objects in the background, pausing this many seconds between passes"),
+            )
+            .arg(
+                Arg::with_name("scrub-rate")
+                    .long("scrub-rate")
+                    .value_name("BYTES")
+                    .requires("scrub-interval")
+                    .help("Bandwidth the scrubber may use per second on average, single downloads run at full speed [default: 1048576]"),
+            )
+            .arg(
+                Arg::with_name("quota-interval")
+                    .long("quota-interval")
+                    .value_name("SECONDS")
+                    .help("Fetch the remaining quota of the Jotta account this often [default: 600]"),
+            )
+            .arg(
+                Arg::with_name("quota-warn")
+                    .long("quota-warn")
+                    .value_name("PERCENT")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Log a warning once the account's usage crosses this level [default: 80 and 95]"),
+            )
+            .arg(
+                Arg::with_name("append-only")
+                    .long("append-only")
+                    .help("Refuse to delete or overwrite anything but locks"),
+            )
+            .arg(
+                Arg::with_name("append-only-repo")
+                    .long("append-only-repo")
+                    .value_name("REPO")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Like --append-only, but only for the given repository"),
+            )
+            .arg(
+                Arg::with_name("read-only")
+                    .long("read-only")
+                    .help("Refuse all uploads and deletes, including the creation of repositories"),
+            )
+            .arg(
+                Arg::with_name("read-only-repo")
+                    .long("read-only-repo")
+                    .value_name("REPO")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Like --read-only, but only for the given repository"),
+            )
+            .arg(
+                Arg::with_name("read-only-allow-locks")
+                    .long("read-only-allow-locks")
+                    .help("Allow locks in read-only repositories, as needed by restic restore"),
+            )
+            .get_matches_from(rclone_compatible_args());
+
+        let unix_socket_mode = matches
+            .value_of("unix-socket-mode")
+            .map_or(Ok(0o600), |mode| u32::from_str_radix(mode, 8))
+            .unwrap_or_else(|_| {
+                clap::Error::with_description(
+                    "--unix-socket-mode expects an octal number, i.e. 660",
+                    clap::ErrorKind::InvalidValue,
+                )
+                .exit()
+            });
+
+        let quota_warn = if matches.is_present("quota-warn") {
+            values_t!(matches, "quota-warn", u8).unwrap_or_else(|e| e.exit())
+        } else {
+            vec![80, 95]
+        };
+
+        let jotta_path = |value: &str| {
+            JottaPath::new(value).unwrap_or_else(|e| {
+                clap::Error::with_description(&e.to_string(), clap::ErrorKind::InvalidValue).exit()
+            })
+        };
+        let base = matches.value_of("base").map(jotta_path).unwrap_or_default();
+        let remote = matches.value_of("remote").map(jotta_path);
+
+        Config {
+            base,
+            stdio: matches.is_present("stdio"),
+            remote,
+            listen: matches.value_of("listen").map(str::to_owned),
+            unix_socket: matches.value_of_os("unix-socket").map(PathBuf::from),
+            unix_socket_mode,
+            htpasswd: matches.value_of_os("htpasswd").map(PathBuf::from),
+            private_repos: matches.is_present("private-repos"),
+            pass_through_auth: matches.is_present("pass-through-auth"),
+            tls: matches.value_of_os("tls-cert").map(|cert| TlsConfig {
+                cert: PathBuf::from(cert),
+                key: PathBuf::from(matches.value_of_os("tls-key").unwrap_or_default()),
+                client_ca: matches.value_of_os("tls-client-ca").map(PathBuf::from),
+            }),
+            incomplete_grace: seconds(&matches, "incomplete-grace"),
+            scrub_interval: seconds(&matches, "scrub-interval"),
+            scrub_rate: number(&matches, "scrub-rate", 1024 * 1024),
+            quota_interval: Duration::from_secs(number(&matches, "quota-interval", 600)),
+            quota_warn,
+            append_only: matches.is_present("append-only"),
+            append_only_repos: repos(&matches, "append-only-repo"),
+            read_only: matches.is_present("read-only"),
+            read_only_repos: repos(&matches, "read-only-repo"),
+            read_only_allow_locks: matches.is_present("read-only-allow-locks"),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Recognizes restic's repository layout in request paths.
+//!
+//! Repositories may be nested at any depth (or be the root folder); the
+//! trailing `config`, `data`, `index`, `keys`, `locks` or `snapshots` segment
+//! tells where the repository ends.
+
+use failure::Fail;
+use jfs::JottaPath;
+
+use crate::restic::TEMP_PREFIX;
+
+/// The folders of a restic repository, besides `config`
+pub const REPO_SUBDIRS: [&str; 5] = ["data", "index", "keys", "locks", "snapshots"];
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum LayoutError {
+    #[fail(display = "{} is no object of a restic repository", path)]
+    UnknownType { path: String },
+    #[fail(display = "{} is no valid object name", name)]
+    InvalidName { name: String },
+    #[fail(display = "{} is inside the folder of another repository", path)]
+    Misplaced { path: String },
+}
+
+/// restic names all objects but `config` after the SHA-256 of their content
+fn is_valid_name(name: &str) -> bool {
+    name.len() == 64
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Whether a folder with files and folders of these names is a repository, i.e. has
+/// both a `config` and a `data` folder, unlike other folders with a `config` such as
+/// `.git`. A `config` still being uploaded counts, i.e. while `restic init` runs.
+pub fn is_repo<'a>(
+    mut file_names: impl Iterator<Item = &'a str>,
+    mut folder_names: impl Iterator<Item = &'a str>,
+) -> bool {
+    let temp_config = format!("{}config", TEMP_PREFIX);
+    file_names.any(|name| name == "config" || name == temp_config)
+        && folder_names.any(|name| name == "data")
+}
+
+/// Whether repositories may be nested in a folder of this name,
+/// which restic's type folders can't hold (see `LayoutError::Misplaced`)
+pub fn may_hold_repos(name: &str) -> bool {
+    !REPO_SUBDIRS.contains(&name)
+}
+
+/// What a request refers to within the repository
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    /// The repository folder itself, i.e. to create it
+    Repo,
+    Config,
+    /// The folder of all objects of a type, i.e. to list it
+    Dir(String),
+    /// An object of the given type and name
+    Object(String, String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RepoPath {
+    /// The folder of the repository
+    pub repo: JottaPath,
+    pub target: Target,
+    /// The complete path, i.e. the repository joined with the target
+    pub path: JottaPath,
+}
+
+impl RepoPath {
+    /// Splits path into repository and target, a trailing slash denotes a folder
+    pub fn parse(path: JottaPath, trailing_slash: bool) -> Result<RepoPath, LayoutError> {
+        let segments = path.segments();
+        let n = segments.len();
+        let is_type = |i: usize| REPO_SUBDIRS.contains(&segments[i].as_str());
+
+        let (depth, target) = if trailing_slash {
+            if n >= 1 && is_type(n - 1) {
+                (n - 1, Target::Dir(segments[n - 1].clone()))
+            } else {
+                (n, Target::Repo)
+            }
+        } else if n >= 1 && segments[n - 1] == "config" {
+            (n - 1, Target::Config)
+        } else if n >= 2 && is_type(n - 2) {
+            (
+                n - 2,
+                Target::Object(segments[n - 2].clone(), segments[n - 1].clone()),
+            )
+        } else {
+            return Err(LayoutError::UnknownType {
+                path: path.to_string(),
+            });
+        };
+
+        if let Target::Object(_, ref name) = target {
+            if !is_valid_name(name) {
+                return Err(LayoutError::InvalidName { name: name.clone() });
+            }
+        }
+
+        // i.e. `data/config` or `data/data/`
+        if depth >= 1 && is_type(depth - 1) {
+            return Err(LayoutError::Misplaced {
+                path: path.to_string(),
+            });
+        }
+
+        let mut repo = path.clone();
+        for _ in depth..n {
+            repo = repo.parent().unwrap_or_default();
+        }
+
+        Ok(RepoPath { repo, target, path })
+    }
+
+    /// The repository's name as given on the command line, i.e. `team/project`
+    pub fn repo_name(&self) -> String {
+        self.repo.segments().join("/")
+    }
+
+    /// The type of object the request refers to, if any
+    pub fn kind(&self) -> Option<&str> {
+        match self.target {
+            Target::Dir(ref kind) | Target::Object(ref kind, _) => Some(kind),
+            Target::Repo | Target::Config => None,
+        }
+    }
+
+    /// Locks are created and removed by every restic command, hence stay writable
+    pub fn is_lock(&self) -> bool {
+        self.kind() == Some("locks")
+    }
+}
+
+#[test]
+fn test_parse() {
+    let parse = |path: &str| RepoPath::parse(JottaPath::new(path).unwrap(), path.ends_with('/'));
+
+    let id = "245bc4c430d393f74fbe7b13325e30dbde9fb0745e50caad57c446c93d20096b";
+
+    let object = parse(&format!("/team/project/data/{}", id)).unwrap();
+    assert_eq!(object.repo, JottaPath::new("/team/project").unwrap());
+    assert_eq!(object.repo_name(), "team/project");
+    assert_eq!(
+        object.target,
+        Target::Object("data".to_owned(), id.to_owned())
+    );
+
+    let config = parse("/config").unwrap();
+    assert!(config.repo.is_root());
+    assert_eq!(config.target, Target::Config);
+
+    assert_eq!(
+        parse("/test/locks/").unwrap().target,
+        Target::Dir("locks".to_owned())
+    );
+    assert!(parse(&format!("/test/locks/{}", id)).unwrap().is_lock());
+    assert_eq!(parse("/a/b/").unwrap().target, Target::Repo);
+    assert_eq!(parse("/").unwrap().target, Target::Repo);
+
+    assert!(parse("/test/foo/abc").is_err());
+    assert!(parse("/test").is_err());
+}
+
+#[test]
+fn test_validation() {
+    let parse = |path: &str| RepoPath::parse(JottaPath::new(path).unwrap(), false);
+
+    assert_eq!(
+        parse("/test/data/abc"),
+        Err(LayoutError::InvalidName {
+            name: "abc".to_owned()
+        })
+    );
+    assert!(parse(&format!("/test/data/{}", "A".repeat(64))).is_err());
+    assert!(parse(&format!("/test/data/.jotta-restic-tmp.{}", "a".repeat(64))).is_err());
+    assert!(parse("/test/data/config").is_err());
+    assert!(parse(&format!("/test/data/keys/{}", "a".repeat(64))).is_err());
+}
+
+#[test]
+fn test_detection() {
+    let data = || vec!["data", "keys"].into_iter();
+    assert!(is_repo(vec!["config", "README"].into_iter(), data()));
+    assert!(is_repo(
+        vec![".jotta-restic-tmp.config"].into_iter(),
+        data()
+    ));
+    assert!(!is_repo(
+        vec!["config.bak", "notes.txt"].into_iter(),
+        data()
+    ));
+    assert!(!is_repo(std::iter::empty(), data()));
+    // i.e. `.git`
+    assert!(!is_repo(
+        vec!["config", "HEAD"].into_iter(),
+        vec!["objects", "refs"].into_iter()
+    ));
+
+    assert!(may_hold_repos("team"));
+    assert!(may_hold_repos("config"));
+    assert!(!may_hold_repos("data"));
+    assert!(!may_hold_repos("locks"));
+}
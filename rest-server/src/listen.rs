@@ -0,0 +1,132 @@
+//! Sockets to serve on besides `--listen`: Unix domain sockets and those passed by systemd
+
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+
+use futures::Stream;
+
+/// The first file descriptor passed by systemd, see sd_listen_fds(3)
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+#[derive(Default)]
+pub struct Listeners {
+    pub tcp: Vec<TcpListener>,
+    pub unix: Vec<UnixListener>,
+}
+
+/// The sockets systemd passed to us (socket activation), if any
+pub fn systemd_listeners() -> Listeners {
+    let mut listeners = Listeners::default();
+
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .map_or(false, |pid| pid == std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<RawFd>().ok())
+        .unwrap_or(0);
+
+    // Don't pass them on to our children
+    for var in &["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+
+    if !for_us {
+        return listeners;
+    }
+
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+        let tcp = unsafe { TcpListener::from_raw_fd(fd) };
+
+        // Unix sockets have no address std knows about
+        if tcp.local_addr().is_ok() {
+            info!("Using TCP socket {:?} from systemd", tcp.local_addr());
+            listeners.tcp.push(tcp);
+        } else {
+            let unix = unsafe { UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+            info!("Using Unix socket {:?} from systemd", unix.local_addr());
+            listeners.unix.push(unix);
+        }
+    }
+
+    listeners
+}
+
+/// Binds to a Unix socket at path, replacing a stale one, with the given permissions.
+/// Anything but a socket at path is left alone.
+pub fn bind_unix(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(ref metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{:?} exists and is no socket", path),
+            ))
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+    }
+
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No socket file name"))?;
+
+    // Bound inside a private folder and moved into place once its permissions are set,
+    // so it's never reachable with the permissions of the umask
+    let private = path.with_file_name(format!(
+        ".{}.{}",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+
+    let bind = || {
+        let socket = private.join(name);
+        let listener = UnixListener::bind(&socket)?;
+        fs::set_permissions(&socket, fs::Permissions::from_mode(mode))?;
+        fs::rename(&socket, path)?;
+        Ok(listener)
+    };
+    let result = bind();
+    fs::remove_dir_all(&private)?;
+
+    result
+}
+
+/// The connections to a Unix socket, to be served with `HttpServer::start_incoming`
+pub fn incoming(
+    listener: UnixListener,
+) -> io::Result<impl Stream<Item = tokio_uds::UnixStream, Error = io::Error>> {
+    let listener = tokio_uds::UnixListener::from_std(listener, &tokio_reactor::Handle::default())?;
+    Ok(listener.incoming())
+}
+
+#[test]
+fn test_bind_unix() {
+    let dir = std::env::temp_dir().join(format!("jotta-restic-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("socket");
+
+    let listener = bind_unix(&path, 0o660).unwrap();
+    let metadata = fs::symlink_metadata(&path).unwrap();
+    assert!(metadata.file_type().is_socket());
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o660);
+
+    // A stale socket is replaced
+    drop(listener);
+    assert!(bind_unix(&path, 0o600).is_ok());
+
+    // Anything else isn't, i.e. a typo in the path
+    let file = dir.join("file");
+    fs::write(&file, "precious").unwrap();
+    assert!(bind_unix(&file, 0o600).is_err());
+    assert_eq!(fs::read_to_string(&file).unwrap(), "precious");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
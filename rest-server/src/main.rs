@@ -1,3 +1,6 @@
+#[macro_use]
+extern crate clap;
+
 #[macro_use]
 extern crate log;
 
@@ -5,13 +8,22 @@ extern crate log;
 extern crate serde_derive;
 
 use actix_web::http::Method;
-use actix_web::middleware::session;
-use actix_web::{middleware, pred, server, App, HttpResponse};
+use actix_web::{middleware, server, App};
 
-fn retrieve_password(username: &str) -> String {
+/// The Jotta password from the keyring, asking for it on the first run. There's no
+/// one to ask when restic started the proxy with `--stdio`, that has to fail.
+fn retrieve_password(username: &str, may_prompt: bool) -> String {
     let keyring = keyring::Keyring::new("jotta-rest", &username);
 
-    keyring.get_password().unwrap_or_else(|_| {
+    keyring.get_password().unwrap_or_else(|e| {
+        if !may_prompt {
+            eprintln!(
+                "No password for Jotta user {} in the keyring ({}), run the proxy once without --stdio to store it",
+                username, e
+            );
+            std::process::exit(1)
+        }
+
         let pw = rpassword::prompt_password_stderr(&format!("Password for user {}: ", username))
             .unwrap();
         keyring
@@ -21,13 +33,43 @@ fn retrieve_password(username: &str) -> String {
     })
 }
 
+mod auth;
+mod config;
+mod layout;
+mod listen;
+mod passthrough;
+mod quota;
+mod readonly;
 mod restic;
+mod scrub;
+mod stdio;
+mod sweep;
+mod tls;
 
 use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub backend: Arc<jfs::JottaClient>,
+    /// The shared Jotta account, absent with pass-through authentication
+    pub backend: Option<Arc<jfs::JottaClient>>,
+    pub config: Arc<config::Config>,
+    /// Only present, if the scrubber runs
+    pub scrub_report: Option<scrub::SharedReport>,
+    /// Only present with the shared Jotta account
+    pub quota: Option<Arc<quota::Quota>>,
+}
+
+pub fn jotta_client(
+    username: &str,
+    password: &str,
+    cassette: Option<&jfs::Cassette>,
+) -> Result<jfs::JottaClient, failure::Error> {
+    // Uploads are moved into place only after Jotta reported the md5 sum we sent
+    let client = jfs::JottaClient::new(username, password)?.verify_uploads(true);
+    Ok(match cassette {
+        Some(cassette) => client.with_transport(jfs::Transport::Record(cassette.clone())),
+        None => client,
+    })
 }
 
 fn main() {
@@ -37,52 +79,216 @@ fn main() {
 
     pretty_env_logger::init();
 
-    let username = env!("JOTTA_USER");
-    let password = retrieve_password(username);
+    let config = Arc::new(config::Config::from_args());
+
+    // Record all exchanges with Jotta, i.e. to turn them into regression tests
+    let cassette = std::env::var_os("JOTTA_RECORD").map(|dir| {
+        info!("Recording Jotta traffic to {:?}", dir);
+        jfs::Cassette::new(dir)
+    });
+
+    let backend = if config.pass_through_auth {
+        None
+    } else {
+        let username = env!("JOTTA_USER");
+        let password = retrieve_password(username, !config.stdio);
+        let client = jotta_client(username, &password, cassette.as_ref()).unwrap_or_else(|e| {
+            eprintln!("Invalid Jotta user {:?}: {}", username, e);
+            std::process::exit(1)
+        });
+        Some(Arc::new(client))
+    };
+    let clients = Arc::new(passthrough::ClientCache::new(cassette, config.base.clone()));
 
     let sys = actix::System::new("jotta-rest-proxy");
 
-    let _addr = server::new(move || {
+    let housekeeping_backend = backend.clone();
+
+    let scrub_report = config
+        .scrub_interval
+        .map(|_| scrub::SharedReport::default());
+
+    let quota = backend
+        .as_ref()
+        .map(|_| Arc::new(quota::Quota::new(config.quota_warn.clone())));
+
+    let htpasswd = config.htpasswd.as_ref().map(|file| {
+        let htpasswd = auth::Htpasswd::from_file(file).unwrap_or_else(|e| {
+            eprintln!("Couldn't read htpasswd file {:?}: {}", file, e);
+            std::process::exit(1)
+        });
+        Arc::new(htpasswd)
+    });
+
+    let state_config = config.clone();
+    let state_scrub_report = scrub_report.clone();
+    let state_quota = quota.clone();
+
+    let app_factory = move || {
         let app_state = AppState {
-            backend: Arc::new(jfs::JottaClient::new(username, &password)),
+            backend: backend.clone(),
+            config: state_config.clone(),
+            scrub_report: state_scrub_report.clone(),
+            quota: state_quota.clone(),
         };
 
-        App::with_state(app_state)
-            .middleware(middleware::Logger::default()) // enable logger
-            // cookie session middleware
-            .middleware(session::SessionStorage::new(
-                session::CookieSessionBackend::signed(&[0; 32]).secure(false),
-            ))
-            .resource("{path}/config", |r| r.route().a(restic::main_handler))
-            .resource("{path}/", |r| {
-                r.method(Method::POST).with_async(restic::create_repo)
-            })
-            .resource("{path}/{type}/", |r| {
-                r.method(Method::GET).a(restic::list_dir)
+        let private_repos = app_state.config.private_repos;
+        let pass_through_auth = app_state.config.pass_through_auth;
+
+        let app = App::with_state(app_state).middleware(middleware::Logger::default()); // enable logger
+
+        let app = match htpasswd {
+            Some(ref htpasswd) => app.middleware(auth::BasicAuth {
+                htpasswd: htpasswd.clone(),
+                private_repos,
+            }),
+            None => app,
+        };
+
+        let app = if pass_through_auth {
+            app.middleware(passthrough::PassThroughAuth {
+                clients: clients.clone(),
             })
-            .resource("{path}/{type}/{name}", |r| {
-                r.route().a(restic::main_handler)
+        } else {
+            app
+        };
+
+        app.middleware(readonly::ReadOnly)
+            .resource("/.jotta-restic/scrub", |r| {
+                r.method(Method::GET).f(scrub::report)
             })
-            // default
-            .default_resource(|r| {
-                // 404 for GET request
-                r.method(Method::GET).f(|_| {
-                    debug!("No route found, calling GET default handler.");
-                    HttpResponse::NotFound().finish()
-                });
-
-                // all requests that are not `GET`
-                r.route().filter(pred::Not(pred::Get())).f(|_| {
-                    debug!("No route found, calling other default handler.");
-                    HttpResponse::MethodNotAllowed()
-                });
+            .resource("/.jotta-restic/quota", |r| {
+                r.method(Method::GET).f(quota::report)
             })
-    })
-    .bind("127.0.0.1:8080")
-    .expect("Can not bind to 127.0.0.1:8080")
-    .shutdown_timeout(0) // <- Set shutdown timeout to 0 seconds (default 60s)
-    .start();
+            // Everything else is told apart by restic's layout, see layout.rs
+            .resource("/{tail:.*}", |r| r.route().a(restic::main_handler))
+    };
+
+    let certificates = config.tls.as_ref().map(|tls_config| {
+        tls::Certificates::load(tls_config).unwrap_or_else(|e| {
+            eprintln!("Couldn't set up TLS: {}", e);
+            std::process::exit(1)
+        })
+    });
+
+    if config.stdio {
+        // stdout belongs to restic, log messages go to stderr. restic speaks HTTP/2
+        // without upgrade, which actix detects by the connection preface.
+        let stdio = stdio::Stdio::new().expect("Can not use stdin/stdout");
+        let connection = futures::stream::once(Ok::<_, std::io::Error>(stdio));
+        server::new(app_factory).start_incoming(connection, false);
+    } else {
+        let mut listeners = listen::systemd_listeners();
+
+        if let Some(ref path) = config.unix_socket {
+            let listener = listen::bind_unix(path, config.unix_socket_mode).unwrap_or_else(|e| {
+                eprintln!("Can not bind to {:?}: {}", path, e);
+                std::process::exit(1)
+            });
+            // Not the listener's address, it was bound at a temporary path
+            println!("Starting http server: {:?}", path);
+            listeners.unix.push(listener);
+        }
+
+        let listen_addr = config.listen.clone().or_else(|| {
+            if listeners.tcp.is_empty() && listeners.unix.is_empty() {
+                Some("127.0.0.1:8080".to_owned())
+            } else {
+                None
+            }
+        });
+
+        // Bound once, so the socket survives restarts of the server (i.e. on SIGHUP)
+        if let Some(ref addr) = listen_addr {
+            let listener = std::net::TcpListener::bind(addr).unwrap_or_else(|e| {
+                eprintln!("Can not bind to {}: {}", addr, e);
+                std::process::exit(1)
+            });
+            println!(
+                "Starting {} server: {}",
+                if config.tls.is_some() {
+                    "https"
+                } else {
+                    "http"
+                },
+                addr
+            );
+            listeners.tcp.push(listener);
+        }
+
+        for listener in listeners.unix {
+            let incoming = listen::incoming(listener).expect("Can not register Unix socket");
+            server::new(app_factory.clone()).start_incoming(incoming, false);
+        }
+
+        if !listeners.tcp.is_empty() {
+            let tcp = listeners.tcp;
+
+            let start_server = move |certificates: Option<&tls::Certificates>| {
+                let mut server = server::new(app_factory.clone());
+
+                for listener in &tcp {
+                    let listener = listener.try_clone()?;
+                    server = match certificates {
+                        Some(certificates) => {
+                            server.listen_ssl(listener, certificates.acceptor()?)?
+                        }
+                        None => server.listen(listener),
+                    };
+                }
+
+                Ok::<_, failure::Error>(server.start())
+            };
+
+            let server = start_server(certificates.as_ref()).unwrap_or_else(|e| {
+                eprintln!("Can not start server: {}", e);
+                std::process::exit(1)
+            });
+
+            if let Some(ref tls_config) = config.tls {
+                use actix::Actor;
+
+                tls::Reloader {
+                    config: tls_config.clone(),
+                    start: move |certificates: &tls::Certificates| start_server(Some(certificates)),
+                    server,
+                }
+                .start();
+            }
+        }
+    }
+
+    if let Some(backend) = housekeeping_backend.clone() {
+        use actix::Actor;
+
+        sweep::Sweeper::new(backend, config.clone()).start();
+    }
+
+    if let (Some(backend), Some(quota)) = (housekeeping_backend.clone(), quota) {
+        use actix::Actor;
+
+        quota::QuotaWatcher {
+            backend,
+            quota,
+            interval: config.quota_interval,
+        }
+        .start();
+    }
+
+    if let (Some(interval), Some(report), Some(backend)) =
+        (config.scrub_interval, scrub_report, housekeeping_backend)
+    {
+        use actix::Actor;
+
+        scrub::Scrubber::new(
+            backend,
+            config.base.clone(),
+            interval,
+            config.scrub_rate,
+            report,
+        )
+        .start();
+    }
 
-    println!("Starting http server: 127.0.0.1:8080");
     let _ = sys.run();
 }
@@ -0,0 +1,260 @@
+//! Serves every restic client with the Jotta account given by its HTTP Basic credentials
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::http::StatusCode;
+use actix_web::middleware::{Middleware, Response, Started};
+use actix_web::{HttpRequest, HttpResponse, Result};
+use futures::future::{err, ok, Either};
+use futures::Future;
+
+use jfs::{Cassette, JottaClient, JottaError, JottaPath, JottaPathError};
+
+use super::AppState;
+use crate::auth::{basic_credentials, unauthorized};
+use crate::restic::CREDENTIALS_REFUSED;
+
+/// Unused clients are dropped after this time, so Jotta checks the credentials again
+const CLIENT_TTL: Duration = Duration::from_secs(60 * 60);
+/// At most this many clients are kept, the least recently used one is dropped first
+const MAX_CLIENTS: usize = 64;
+
+/// The Jotta client of a request, stored in the request's extensions
+#[derive(Clone)]
+pub struct RequestBackend(pub Arc<JottaClient>);
+
+struct CachedClient {
+    /// SHA-256 of the password
+    key: String,
+    client: Arc<JottaClient>,
+    last_used: Instant,
+}
+
+/// One client per user, so connections are reused across requests.
+/// Only credentials Jotta accepted end up here.
+pub struct ClientCache {
+    cassette: Option<Cassette>,
+    base: JottaPath,
+    /// By username
+    clients: Mutex<HashMap<String, CachedClient>>,
+}
+
+impl ClientCache {
+    pub fn new(cassette: Option<Cassette>, base: JottaPath) -> ClientCache {
+        ClientCache {
+            cassette,
+            base,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn cached(&self, username: &str, key: &str) -> Option<Arc<JottaClient>> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|_, cached| now.duration_since(cached.last_used) < CLIENT_TTL);
+
+        let cached = clients
+            .get_mut(username)
+            .filter(|cached| cached.key == key)?;
+        cached.last_used = now;
+        Some(cached.client.clone())
+    }
+
+    fn insert(&self, username: String, key: String, client: Arc<JottaClient>) {
+        let mut clients = self.clients.lock().unwrap();
+
+        if clients.len() >= MAX_CLIENTS && !clients.contains_key(&username) {
+            let oldest = clients
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(username, _)| username.clone());
+            if let Some(oldest) = oldest {
+                clients.remove(&oldest);
+            }
+        }
+
+        clients.insert(
+            username,
+            CachedClient {
+                key,
+                client,
+                last_used: Instant::now(),
+            },
+        );
+    }
+
+    /// Forgets the client for the credentials, i.e. once Jotta refuses them
+    pub fn evict(&self, username: &str, password: &str) {
+        let key = crate::restic::sha256_hex(password.as_bytes());
+        let mut clients = self.clients.lock().unwrap();
+
+        if clients
+            .get(username)
+            .map_or(false, |cached| cached.key == key)
+        {
+            clients.remove(username);
+        }
+    }
+
+    /// The client for the credentials. New ones are checked with Jotta
+    /// (by fetching the account information) before they are cached.
+    pub fn get(
+        self: Arc<Self>,
+        username: &str,
+        password: &str,
+    ) -> impl Future<Item = Arc<JottaClient>, Error = failure::Error> {
+        let key = crate::restic::sha256_hex(password.as_bytes());
+        if let Some(client) = self.cached(username, &key) {
+            return Either::A(ok(client));
+        }
+
+        let client = match crate::jotta_client(username, password, self.cassette.as_ref()) {
+            Ok(client) => Arc::new(client),
+            Err(e) => return Either::A(err(e)),
+        };
+        let username = username.to_owned();
+
+        Either::B(client.user().and_then(move |_| {
+            info!("New Jotta client for user {}", username);
+            self.insert(username, key, client.clone());
+
+            // There is no shared account to prepare at startup, hence do it now.
+            // Temporaries are left to the grace period, as sweeping takes a while.
+            crate::restic::create_base(client.clone(), self.base.clone())
+                .then(move |_| Ok::<_, failure::Error>(client))
+        }))
+    }
+}
+
+/// Whether Jotta refused the credentials, or would, as the username isn't even a valid path
+fn is_refused(error: &failure::Error) -> bool {
+    let root_cause = error.find_root_cause();
+
+    root_cause.downcast_ref::<JottaPathError>().is_some()
+        || root_cause
+            .downcast_ref::<JottaError>()
+            .map_or(false, |e| e.code == 401 || e.code == 403)
+}
+
+/// Refuses requests without credentials or with credentials Jotta refuses,
+/// otherwise attaches the user's Jotta client.
+pub struct PassThroughAuth {
+    pub clients: Arc<ClientCache>,
+}
+
+impl Middleware<AppState> for PassThroughAuth {
+    fn start(&self, req: &HttpRequest<AppState>) -> Result<Started> {
+        let (username, password) = match basic_credentials(req) {
+            Some(credentials) => credentials,
+            None => return Ok(Started::Response(unauthorized())),
+        };
+
+        let req = req.clone();
+        let fut = self
+            .clients
+            .clone()
+            .get(&username, &password)
+            .then(move |res| match res {
+                Ok(client) => {
+                    req.extensions_mut().insert(RequestBackend(client));
+                    Ok::<_, actix_web::Error>(None)
+                }
+                Err(ref e) if is_refused(e) => {
+                    warn!("Refusing the credentials of user {:?}: {}", username, e);
+                    Ok(Some(unauthorized()))
+                }
+                Err(e) => Ok(Some(crate::restic::error_response(&e))),
+            });
+
+        Ok(Started::Future(Box::new(fut)))
+    }
+
+    /// Jotta refused cached credentials, i.e. as the password was changed since.
+    /// restic gets to ask for them again, instead of a `502`.
+    fn response(&self, req: &HttpRequest<AppState>, resp: HttpResponse) -> Result<Response> {
+        if resp.status() != StatusCode::BAD_GATEWAY || resp.reason() != CREDENTIALS_REFUSED {
+            return Ok(Response::Done(resp));
+        }
+
+        if let Some((username, password)) = basic_credentials(req) {
+            warn!("Jotta refuses the credentials of user {:?} now", username);
+            self.clients.evict(&username, &password);
+        }
+        Ok(Response::Done(unauthorized()))
+    }
+}
+
+#[test]
+fn test_client_cache() {
+    let cache = ClientCache::new(None, JottaPath::root());
+    let client = || Arc::new(JottaClient::new("alice", "secret").unwrap());
+
+    cache.insert("alice".to_owned(), "key".to_owned(), client());
+    assert!(cache.cached("alice", "key").is_some());
+    assert!(cache.cached("alice", "other key").is_none());
+    assert!(cache.cached("bob", "key").is_none());
+
+    cache.evict("alice", "wrong");
+    assert!(cache.cached("alice", "key").is_some());
+
+    for i in 0..2 * MAX_CLIENTS {
+        cache.insert(format!("user{}", i), "key".to_owned(), client());
+    }
+    assert_eq!(cache.clients.lock().unwrap().len(), MAX_CLIENTS);
+}
+
+#[test]
+fn test_invalid_username() {
+    // The username becomes part of every path, `..` would leave the account
+    for username in &["..", "alice/bob", ""] {
+        let error = JottaClient::new(username, "secret").err().unwrap();
+        assert!(is_refused(&error));
+    }
+}
+
+#[test]
+fn test_refused_later() {
+    use actix_web::http::header;
+    use actix_web::test::TestRequest;
+
+    let clients = Arc::new(ClientCache::new(None, JottaPath::root()));
+    let key = crate::restic::sha256_hex(b"secret");
+    let client = Arc::new(JottaClient::new("alice", "secret").unwrap());
+    clients.insert("alice".to_owned(), key.clone(), client);
+
+    let auth = PassThroughAuth {
+        clients: clients.clone(),
+    };
+    let req = TestRequest::with_state(crate::restic::replay_state("none"))
+        // alice:secret
+        .header(header::AUTHORIZATION, "Basic YWxpY2U6c2VjcmV0")
+        .finish();
+    let refused = failure::Error::from(JottaError {
+        code: 401,
+        ..Default::default()
+    });
+
+    match auth
+        .response(&req, crate::restic::error_response(&refused))
+        .unwrap()
+    {
+        Response::Done(resp) => assert_eq!(resp.status(), StatusCode::UNAUTHORIZED),
+        _ => panic!("Expected a response right away"),
+    }
+    assert!(clients.cached("alice", &key).is_none());
+
+    // Other failures of Jotta pass
+    let failed = failure::Error::from(JottaError {
+        code: 500,
+        ..Default::default()
+    });
+    match auth
+        .response(&req, crate::restic::error_response(&failed))
+        .unwrap()
+    {
+        Response::Done(resp) => assert_eq!(resp.status(), StatusCode::BAD_GATEWAY),
+        _ => panic!("Expected a response right away"),
+    }
+}
@@ -0,0 +1,180 @@
+//! Keeps track of the space left in the Jotta account.
+//!
+//! The account information is fetched periodically and after each upload, so
+//! uploads which can't fit are refused before their body is transferred.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix::prelude::*;
+use chrono::{DateTime, Utc};
+use futures::Future;
+
+use actix_web::{HttpRequest, HttpResponse};
+use jfs::{JottaClient, User};
+
+use crate::AppState;
+
+/// What we know about the account
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct QuotaStatus {
+    /// In bytes, `None` if the account is unlimited
+    pub capacity: Option<i64>,
+    /// In bytes
+    pub usage: i64,
+    /// Jotta refuses uploads, i.e. because the account is full
+    pub write_locked: bool,
+    pub last_update: Option<DateTime<Utc>>,
+    /// The highest of the warning thresholds (in percent) the usage crossed
+    pub warning: Option<u8>,
+}
+
+pub struct Quota {
+    /// Usage levels in percent, which are worth a warning
+    thresholds: Vec<u8>,
+    status: Mutex<QuotaStatus>,
+}
+
+impl Quota {
+    pub fn new(mut thresholds: Vec<u8>) -> Quota {
+        thresholds.sort();
+        Quota {
+            thresholds,
+            status: Mutex::new(QuotaStatus::default()),
+        }
+    }
+
+    pub fn status(&self) -> QuotaStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Free space in bytes, `None` if unlimited or not known (yet)
+    pub fn remaining(&self) -> Option<i64> {
+        let status = self.status.lock().unwrap();
+        if status.write_locked {
+            return Some(0);
+        }
+        status
+            .capacity
+            .map(|capacity| (capacity - status.usage).max(0))
+    }
+
+    /// Whether an upload of the given size is known not to fit
+    pub fn exceeded_by(&self, size: u64) -> bool {
+        self.remaining()
+            .map_or(false, |remaining| size > remaining as u64)
+    }
+
+    pub fn update(&self, user: &User) {
+        let mut status = self.status.lock().unwrap();
+
+        status.capacity = if user.capacity < 0 {
+            None
+        } else {
+            Some(user.capacity)
+        };
+        status.usage = user.usage;
+        status.write_locked = user.write_locked || user.quota_write_locked;
+        status.last_update = Some(Utc::now());
+
+        let warning = self.crossed_threshold(status.usage, status.capacity);
+        if warning > status.warning {
+            warn!(
+                "The Jotta account uses {} of {} bytes, more than {}%",
+                status.usage,
+                status.capacity.unwrap_or_default(),
+                warning.unwrap_or_default()
+            );
+        } else if warning < status.warning {
+            info!(
+                "The Jotta account uses {} bytes, back below {}%",
+                status.usage,
+                status.warning.unwrap_or_default()
+            );
+        }
+        status.warning = warning;
+
+        if status.write_locked {
+            error!("Jotta refuses uploads to the account, is it full?");
+        }
+    }
+
+    fn crossed_threshold(&self, usage: i64, capacity: Option<i64>) -> Option<u8> {
+        let capacity = capacity.filter(|&c| c > 0)?;
+        let percent = usage.max(0) as u128 * 100 / capacity as u128;
+
+        self.thresholds
+            .iter()
+            .cloned()
+            .filter(|&threshold| percent >= u128::from(threshold))
+            .last()
+    }
+}
+
+/// Fetches the account information, problems are only logged
+pub fn refresh(backend: Arc<JottaClient>, quota: Arc<Quota>) -> impl Future<Item = (), Error = ()> {
+    backend
+        .user()
+        .map(move |user| quota.update(&user))
+        .map_err(|e| warn!("Couldn't fetch the Jotta account information: {}", e))
+}
+
+/// Refreshes the quota periodically
+pub struct QuotaWatcher {
+    pub backend: Arc<JottaClient>,
+    pub quota: Arc<Quota>,
+    pub interval: Duration,
+}
+
+impl Actor for QuotaWatcher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.spawn(refresh(self.backend.clone(), self.quota.clone()).into_actor(self));
+        ctx.run_interval(self.interval, |act, ctx| {
+            ctx.spawn(refresh(act.backend.clone(), act.quota.clone()).into_actor(act));
+        });
+    }
+}
+
+/// The quota as JSON, 404 if it isn't tracked
+pub fn report(req: &HttpRequest<AppState>) -> HttpResponse {
+    match req.state().quota {
+        Some(ref quota) => HttpResponse::Ok().json(quota.status()),
+        None => HttpResponse::NotFound()
+            .reason("Quota isn't tracked")
+            .finish(),
+    }
+}
+
+#[test]
+fn test_quota() {
+    let quota = Quota::new(vec![90, 80]);
+    assert_eq!(quota.remaining(), None);
+    assert!(!quota.exceeded_by(1 << 40));
+
+    let mut user = User {
+        capacity: 1000,
+        usage: 850,
+        ..Default::default()
+    };
+    quota.update(&user);
+    assert_eq!(quota.remaining(), Some(150));
+    assert_eq!(quota.status().warning, Some(80));
+    assert!(quota.exceeded_by(151));
+    assert!(!quota.exceeded_by(150));
+
+    user.usage = 1200;
+    quota.update(&user);
+    assert_eq!(quota.remaining(), Some(0));
+    assert_eq!(quota.status().warning, Some(90));
+
+    user.capacity = -1;
+    quota.update(&user);
+    assert_eq!(quota.remaining(), None);
+    assert_eq!(quota.status().warning, None);
+
+    user.quota_write_locked = true;
+    quota.update(&user);
+    assert!(quota.exceeded_by(1));
+}
@@ -0,0 +1,36 @@
+use actix_web::http::Method;
+use actix_web::middleware::{Middleware, Started};
+use actix_web::{HttpRequest, HttpResponse, Result};
+
+use super::AppState;
+use crate::restic::repo_path;
+
+/// Refuses every request, which would change a read-only repository
+pub struct ReadOnly;
+
+impl Middleware<AppState> for ReadOnly {
+    fn start(&self, req: &HttpRequest<AppState>) -> Result<Started> {
+        if *req.method() == Method::GET || *req.method() == Method::HEAD {
+            return Ok(Started::Done);
+        }
+
+        // Invalid paths and unknown types are refused by the handlers later on
+        let path = match repo_path(req) {
+            Ok(path) => path,
+            Err(_) => return Ok(Started::Done),
+        };
+        let config = &req.state().config;
+        if config.is_read_only(&path.repo_name())
+            && !(path.is_lock() && config.read_only_allow_locks)
+        {
+            warn!("Refusing {} {} in read-only mode", req.method(), req.path());
+            Ok(Started::Response(
+                HttpResponse::Forbidden()
+                    .reason("Repository is read-only")
+                    .finish(),
+            ))
+        } else {
+            Ok(Started::Done)
+        }
+    }
+}
@@ -1,10 +1,12 @@
 #[derive(Debug, Deserialize)]
 pub struct CreateQuery {
+    #[serde(default)]
     create: bool,
 }
 
 use std::sync::Arc;
 
+use bytes::Bytes;
 use futures::future::{ok, result, Future};
 use futures::Stream;
 
@@ -13,9 +15,12 @@ use actix_web::http::Method;
 use actix_web::error::Error;
 use actix_web::{AsyncResponder, FutureResponse, HttpMessage, HttpRequest, HttpResponse, Query};
 
-use actix_web::http::StatusCode;
+use actix_web::http::{header, StatusCode};
 
-use jfs::{File as JottaFile, Folder as JottaFolder, JottaError, Object};
+use jfs::{
+    is_not_found, File as JottaFile, Folder as JottaFolder, IncompleteError, IntegrityError,
+    JottaClient, JottaError, JottaPath, Object,
+};
 
 #[derive(Serialize, Debug)]
 struct DirListEntry {
@@ -27,23 +32,359 @@ impl From<JottaFile> for DirListEntry {
     fn from(f: JottaFile) -> DirListEntry {
         DirListEntry {
             name: f.name,
-            size: f.size,
+            size: f.size(),
         }
     }
 }
 
 use super::AppState;
+pub use crate::layout::REPO_SUBDIRS;
+use crate::layout::{self, LayoutError, RepoPath, Target};
+use crate::passthrough::RequestBackend;
 
+/// Media types of the REST API versions, used for content negotiation of listings
+pub const API_V1: &str = "application/vnd.x.restic.rest.v1";
+pub const API_V2: &str = "application/vnd.x.restic.rest.v2";
+
+/// Uploads are written to a hidden file with this prefix, which is moved into place afterwards
+pub const TEMP_PREFIX: &str = ".jotta-restic-tmp.";
+
+/// restic must not see files, which are in the trash, were never uploaded completely
+/// or are still being uploaded
+pub(crate) fn is_visible(file: &JottaFile) -> bool {
+    file.deleted.is_none() && file.is_complete() && !file.name.starts_with(TEMP_PREFIX)
+}
+
+/// restic names everything but `config` after the SHA-256 of its content.
+/// Returns the expected (lower case hex) hash for an upload request, if any.
+fn expected_hash(req: &HttpRequest<AppState>) -> Option<String> {
+    match repo_path(req).ok()?.target {
+        Target::Object(_, name) => Some(name.to_lowercase()),
+        _ => None,
+    }
+}
+
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// The hidden name in the same folder, the data for path is uploaded to
+fn temp_path(path: &JottaPath) -> Result<JottaPath, failure::Error> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| failure::err_msg("Can't upload to the root folder"))?;
+
+    path.parent()
+        .unwrap_or_default()
+        .join(&format!("{}{}", TEMP_PREFIX, name))
+}
+
+/// Creates the base folder, including its parents
+pub fn create_base(
+    backend: Arc<JottaClient>,
+    base: JottaPath,
+) -> impl Future<Item = (), Error = ()> {
+    let mut folders = Vec::new();
+    let mut folder = base.clone();
+    while let Some(parent) = folder.parent() {
+        folders.push(folder);
+        folder = parent;
+    }
+    folders.reverse();
+
+    futures::stream::iter_ok(folders)
+        .for_each(move |folder| backend.mkdir(&folder).map(|_| ()))
+        .map_err(move |e| error!("Couldn't create base folder {}: {}", base, e))
+}
+
+/// Folders listed at once while looking for repositories
+const LIST_CONCURRENCY: usize = 4;
+
+/// Whether the listed folder is a repository, ignoring anything in the trash
+fn is_repo_folder(dir: &JottaFolder) -> bool {
+    layout::is_repo(
+        dir.files
+            .iter()
+            .filter(|f| f.deleted.is_none())
+            .map(|f| f.name.as_str()),
+        dir.folders
+            .iter()
+            .filter(|f| f.deleted.is_none())
+            .map(|f| f.name.as_str()),
+    )
+}
+
+/// All repositories at or below root, with the listing of their folder. Repositories
+/// may be nested at any depth, so folders are searched level by level, a few at a time.
+/// Neither restic's type folders nor the folders of repositories are searched.
+/// Folders which can't be listed are skipped, unless it is root.
+pub(crate) fn find_repos(
+    backend: Arc<JottaClient>,
+    root: JottaPath,
+) -> impl Future<Item = Vec<(JottaPath, JottaFolder)>, Error = failure::Error> {
+    use futures::future::{loop_fn, Either, Loop};
+    use futures::stream::iter_ok;
+
+    backend.list(&root).and_then(move |dir| {
+        loop_fn(
+            (Vec::new(), vec![(root, dir)]),
+            move |(mut found, level)| {
+                let mut below = Vec::new();
+                for (path, dir) in level {
+                    if is_repo_folder(&dir) {
+                        found.push((path, dir));
+                    } else {
+                        below.extend(
+                            dir.folders
+                                .iter()
+                                .filter(|f| f.deleted.is_none() && layout::may_hold_repos(&f.name))
+                                .filter_map(|f| path.join(&f.name).ok()),
+                        );
+                    }
+                }
+
+                if below.is_empty() {
+                    return Either::A(ok(Loop::Break(found)));
+                }
+
+                let backend = backend.clone();
+                Either::B(
+                    iter_ok::<_, failure::Error>(below)
+                        .map(move |path| {
+                            backend.list(&path).then(move |listed| match listed {
+                                Ok(dir) => Ok(Some((path, dir))),
+                                Err(e) => {
+                                    warn!("Couldn't look for repositories in {}: {}", path, e);
+                                    Ok(None)
+                                }
+                            })
+                        })
+                        .buffered(LIST_CONCURRENCY)
+                        .filter_map(|listed| listed)
+                        .collect()
+                        .map(move |level| Loop::Continue((found, level))),
+                )
+            },
+        )
+    })
+}
+
+/// The object at path as restic sees it, i.e. `None` for objects
+/// in the trash or whose upload never completed
+fn lookup(
+    backend: &JottaClient,
+    path: &JottaPath,
+) -> impl Future<Item = Option<Object>, Error = failure::Error> {
+    use futures::future::err;
+
+    backend
+        .query_object(path)
+        .map(|obj| {
+            if let Some(when) = obj.deleted() {
+                debug!("Only exists in trash; deleted at {}", when);
+                return None;
+            }
+
+            debug!("This object exists: {:?}", obj);
+            match obj {
+                Object::File(ref f) if !f.is_complete() => {
+                    debug!("Upload never completed, hence absent");
+                    None
+                }
+                obj => Some(obj),
+            }
+        })
+        .or_else(|error: failure::Error| {
+            if is_not_found(&error) {
+                ok(None)
+            } else {
+                err(error)
+            }
+        })
+}
+
+/// The path of the request as restic sees it, i.e. `<repo>/<type>/<name>`.
+/// In stdio mode, the URL lacks the repository, which is given on the command line.
+pub(crate) fn repo_path(req: &HttpRequest<AppState>) -> Result<RepoPath, failure::Error> {
+    let path = JottaPath::from_url_path(req.path())?;
+
+    // restic never sends `.` or `..`, which would have been resolved above
+    let segments = req.path().split('/').filter(|s| !s.is_empty()).count();
+    if segments != path.segments().len() {
+        return Err(failure::err_msg(format!(
+            "{:?} contains relative segments",
+            req.path()
+        )));
+    }
+    let path = match req.state().config.remote {
+        Some(ref remote) => remote.concat(&path),
+        None => path,
+    };
+
+    Ok(RepoPath::parse(path, req.path().ends_with('/'))?)
+}
+
+/// The name of the repository, i.e. `team/project`
+fn repo_name(req: &HttpRequest<AppState>) -> String {
+    repo_path(req)
+        .map(|path| path.repo_name())
+        .unwrap_or_default()
+}
+
+fn is_lock(req: &HttpRequest<AppState>) -> bool {
+    repo_path(req).map_or(false, |path| path.is_lock())
+}
+
+/// The Jotta account to serve the request with
+fn backend(req: &HttpRequest<AppState>) -> Arc<JottaClient> {
+    req.extensions()
+        .get::<RequestBackend>()
+        .map(|b| b.0.clone())
+        .or_else(|| req.state().backend.clone())
+        .expect("Neither a shared nor a per-request Jotta client")
+}
+
+/// The Jotta path for the request; invalid paths are a bad request.
+/// It's below the base folder, which restic doesn't know about.
+fn jotta_path(req: &HttpRequest<AppState>) -> Result<JottaPath, Error> {
+    let base = &req.state().config.base;
+
+    repo_path(req)
+        .map(|path| base.concat(&path.path))
+        .map_err(|e| {
+            info!("Refusing invalid path {:?}: {}", req.path(), e);
+            actix_web::error::ErrorBadRequest(e)
+        })
+}
+
+/// Reason phrase of the `502` for credentials Jotta refused, so pass-through
+/// authentication can tell restic to ask for them again (see `PassThroughAuth`)
+pub(crate) const CREDENTIALS_REFUSED: &str = "Jottacloud refused the credentials";
+
+/// Translates what went wrong with Jotta into a status restic can make sense of,
+/// with a text body telling the upstream cause
+pub(crate) fn error_response(error: &failure::Error) -> HttpResponse {
+    use actix_web::client::SendRequestError;
+
+    let root_cause = error.find_root_cause();
+
+    let (status, message) = if let Some(e) = error.downcast_ref::<IncompleteError>() {
+        (StatusCode::NOT_FOUND, e.to_string())
+    } else if let Some(e) = root_cause.downcast_ref::<JottaError>() {
+        match e.code {
+            404 => (StatusCode::NOT_FOUND, e.to_string()),
+            401 | 403 => (
+                StatusCode::BAD_GATEWAY,
+                format!("{}: {}", CREDENTIALS_REFUSED, e),
+            ),
+            _ if e.is_quota_exceeded() => (
+                StatusCode::INSUFFICIENT_STORAGE,
+                format!("The Jottacloud account is full: {}", e),
+            ),
+            _ => (StatusCode::BAD_GATEWAY, e.to_string()),
+        }
+    } else if let Some(SendRequestError::Timeout) = root_cause.downcast_ref::<SendRequestError>() {
+        (
+            StatusCode::GATEWAY_TIMEOUT,
+            "Jottacloud didn't answer in time".to_owned(),
+        )
+    } else if let Some(e) = error.downcast_ref::<IntegrityError>() {
+        (StatusCode::BAD_GATEWAY, e.to_string())
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
+    };
+
+    if status == StatusCode::NOT_FOUND {
+        debug!("{}", message);
+    } else {
+        warn!("Answering {}: {}", status, message);
+    }
+
+    let mut response = HttpResponse::build(status);
+    if status == StatusCode::BAD_GATEWAY && message.starts_with(CREDENTIALS_REFUSED) {
+        response.reason(CREDENTIALS_REFUSED);
+    }
+
+    response
+        .content_type("text/plain; charset=utf-8")
+        .body(message)
+}
+
+/// For use with `or_else` at the end of the handlers
+fn respond(error: failure::Error) -> Result<HttpResponse, Error> {
+    Ok(error_response(&error))
+}
+
+/// The methods restic's REST API knows for the target
+fn allowed_methods(target: &Target) -> &'static str {
+    match target {
+        Target::Repo => "POST",
+        Target::Dir(_) => "GET",
+        Target::Config | Target::Object(..) => "GET, HEAD, POST, DELETE",
+    }
+}
+
+pub fn method_not_allowed(req: &HttpRequest<AppState>, target: &Target) -> HttpResponse {
+    info!("Refusing {} {}", req.method(), req.path());
+    HttpResponse::MethodNotAllowed()
+        .header(header::ALLOW, allowed_methods(target))
+        .finish()
+}
+
+/// Whether the client asked for version 2 of the REST API, otherwise version 1 is used
+fn wants_v2(req: &HttpRequest<AppState>) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map_or(false, |accept| {
+            accept
+                .split(',')
+                .any(|media_type| media_type.trim().starts_with(API_V2))
+        })
+}
+
+/// Entry point for all requests, which tells the repository from the object
+/// by restic's layout, so repositories may be nested or the root folder.
 pub fn main_handler(req: &HttpRequest<AppState>) -> FutureResponse<HttpResponse, Error> {
     // Returns “200 OK” if the repository has a configuration, an HTTP error otherwise.
     debug!("main_handler {:?}", req);
 
-    match *req.method() {
-        Method::GET => download(req).responder(),
-        Method::POST => upload(req).responder(),
-        Method::HEAD => exists(req).responder(),
-        Method::DELETE => delete(req).responder(),
-        _ => result(Ok(HttpResponse::MethodNotAllowed().finish())).responder(),
+    let target = match repo_path(req) {
+        Ok(path) => path.target,
+        Err(e) => {
+            info!("Refusing {}: {}", req.path(), e);
+            let response = match e.downcast_ref::<LayoutError>() {
+                Some(LayoutError::UnknownType { .. }) => {
+                    HttpResponse::NotFound().reason("Unknown type").finish()
+                }
+                // Such an object can't exist, just like in restic's rest-server
+                Some(LayoutError::InvalidName { .. }) => {
+                    HttpResponse::NotFound().reason("Invalid name").finish()
+                }
+                _ => HttpResponse::BadRequest().reason("Invalid path").finish(),
+            };
+            return result(Ok(response)).responder();
+        }
+    };
+
+    match (&target, req.method().clone()) {
+        (Target::Repo, Method::POST) => create_repo(req).responder(),
+        (Target::Dir(_), Method::GET) => list_dir(req).responder(),
+        (Target::Config, Method::GET) | (Target::Object(..), Method::GET) => {
+            download(req).responder()
+        }
+        (Target::Config, Method::POST) | (Target::Object(..), Method::POST) => {
+            upload(req).responder()
+        }
+        (Target::Config, Method::HEAD) | (Target::Object(..), Method::HEAD) => {
+            exists(req).responder()
+        }
+        (Target::Config, Method::DELETE) | (Target::Object(..), Method::DELETE) => {
+            delete(req).responder()
+        }
+        _ => result(Ok(method_not_allowed(req, &target))).responder(),
     }
 }
 
@@ -53,130 +394,161 @@ pub fn main_handler(req: &HttpRequest<AppState>) -> FutureResponse<HttpResponse,
 pub fn exists(
     req: &HttpRequest<AppState>,
 ) -> impl Future<Item = HttpResponse, Error = actix_web::Error> + 'static {
-    use futures::future::err;
-
-    let path = req.path();
-    info!("exists {:?}: ", path);
-
-    req.state()
-        .backend
-        .query_object(path)
-        .and_then(|obj| {
-            ok(obj
-                .deleted()
-                .map(|when| {
-                    debug!("Only exists in trash; deleted at {}", when);
-                    HttpResponse::NotFound().finish()
-                })
-                .unwrap_or_else(|| {
-                    debug!("This object exists: {:?}", obj);
-                    match obj {
-                        Object::File(f) => {
-                            HttpResponse::Ok().content_length(f.size as u64).finish()
-                        }
-                        Object::Folder(_) => HttpResponse::Ok().finish(),
-                    }
-                }))
-        })
-        .or_else(|error: failure::Error| {
-            error
-                .find_root_cause()
-                .downcast_ref::<JottaError>()
-                .and_then(|ref e| {
-                    if e.code == 404 {
-                        Some(ok(HttpResponse::NotFound().finish()))
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or(err(error))
-        })
-        .map_err(Error::from)
+    let backend = backend(req);
+
+    result(jotta_path(req)).and_then(move |path| {
+        info!("exists {:?}: ", path);
+
+        lookup(&backend, &path)
+            .map(|obj| match obj {
+                Some(Object::File(f)) => {
+                    HttpResponse::Ok().content_length(f.size() as u64).finish()
+                }
+                Some(Object::Folder(_)) => HttpResponse::Ok().finish(),
+                None => HttpResponse::NotFound().finish(),
+            })
+            .or_else(respond)
+    })
 }
 
 /// Makes sure that the directory itself as well as certain subdirs exist
 ///
 /// These subdirs are:
 ///    ["data", "index", "keys", "locks", "snapshots"]
+///
+/// Like restic's rest-server, only `POST /<repo>/?create=true` is supported.
 pub fn create_repo(
-    (opt, req): (Query<CreateQuery>, HttpRequest<AppState>),
+    req: &HttpRequest<AppState>,
 ) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
-    let basedir = req.path().to_owned();
+    use actix_web::FromRequest;
 
-    info!("create repo {:?} at {:?}", opt, basedir);
+    let backend = backend(req);
+    let opt = Query::<CreateQuery>::extract(req);
 
-    let backend: Arc<jfs::JottaClient> = req.state().backend.clone();
+    result(opt.and_then(|opt| Ok((opt, jotta_path(req)?)))).and_then(move |(opt, basedir)| {
+        use futures::future::Either;
 
-    req.state()
-        .backend
-        .mkdir(&basedir)
-        .and_then(move |_| {
-            use futures::future::join_all;
+        info!("create repo {:?} at {:?}", opt, basedir);
 
-            join_all(
-                ["data", "index", "keys", "locks", "snapshots"]
-                    .iter()
-                    .map(move |subdir| {
-                        let mut full_path = basedir.clone();
-                        full_path.push('/');
-                        full_path.push_str(subdir);
+        if !opt.create {
+            return Either::A(ok(HttpResponse::BadRequest()
+                .reason("Only creating is supported")
+                .finish()));
+        }
 
-                        backend.mkdir(&full_path)
-                    }),
-            )
-        })
-        .map_err(Error::from)
-        .and_then(|_| Ok(HttpResponse::Ok().finish()))
+        Either::B(
+            backend
+                .mkdir(&basedir)
+                .and_then(move |_| {
+                    use futures::future::join_all;
+
+                    join_all(
+                        REPO_SUBDIRS
+                            .iter()
+                            .map(move |subdir| {
+                                let backend = backend.clone();
+                                result(basedir.join(subdir))
+                                    .and_then(move |full_path| backend.mkdir(&full_path))
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                })
+                .map(|_| HttpResponse::Ok().finish())
+                .or_else(respond),
+        )
+    })
 }
 
 // Returns the content of the blob with the given name and type if it is stored in the repository, “404 not found” otherwise.
 //
 // If the request specifies a partial read with a Range header field, then the status code of the response is 206 instead of 200 and the response only contains the specified range.
 //
-// Response format: binary/octet-stream
+// Response format: application/octet-stream
 pub fn download(
     req: &HttpRequest<AppState>,
 ) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
-    let path = req.path();
-
-    info!("Download request {}", path);
-
-    //TODO: partial read
-
-    req.state().backend.download(path).then(|res| {
-        debug!("Response for download request: {:?}", res);
-        match res {
-            Ok(b) => Ok(HttpResponse::Ok()
-                .content_type("binary/octet-stream")
-                .body(b)),
-            Err(error) => error
-                // Forward Jotta's error codes (such as 404) to our server
-                .find_root_cause()
-                .downcast_ref::<JottaError>()
-                .map(|ref e| {
-                    Ok(HttpResponse::new(
-                        StatusCode::from_u16(e.code as u16).unwrap(),
-                    ))
-                })
-                .unwrap_or(Err(error))
-                .map_err(Error::from),
-        }
-    }) // Needed for streaming response
+    let backend = backend(req);
+
+    result(jotta_path(req)).and_then(move |path| {
+        info!("Download request {}", path);
+
+        //TODO: partial read
+
+        backend
+            .download(&path)
+            .map(|b| {
+                HttpResponse::Ok()
+                    .content_type("application/octet-stream")
+                    .body(b)
+            })
+            .or_else(respond) // Needed for streaming response
+    })
 }
 
 pub fn delete(
     req: &HttpRequest<AppState>,
 ) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
+    use futures::future::Either;
+
     // Returns “200 OK” if the repository has a configuration, an HTTP error otherwise.
     info!("delete request {:?}", req);
 
-    let path = req.path();
+    let backend = backend(req);
+
+    if req.state().config.is_append_only(&repo_name(req)) && !is_lock(req) {
+        warn!("Refusing to delete {} in append-only mode", req.path());
+        return Either::A(ok(HttpResponse::Forbidden()
+            .reason("Repository is append-only")
+            .finish()));
+    }
+
+    Either::B(result(jotta_path(req)).and_then(move |path| {
+        backend
+            .delete(&path)
+            .map(|_| HttpResponse::Ok().finish())
+            .or_else(respond)
+    }))
+}
+
+/// Whether the file has exactly the content of body, according to its size and md5 sum
+fn is_identical(file: &JottaFile, body: &[u8]) -> bool {
+    file.size() == body.len()
+        && file.md5().map_or(false, |md5| {
+            md5.eq_ignore_ascii_case(&format!("{:x}", md5::compute(body)))
+        })
+}
+
+/// Uploads body to path, without ever exposing partial content there
+fn store(
+    backend: Arc<JottaClient>,
+    path: JottaPath,
+    body: Bytes,
+) -> impl Future<Item = HttpResponse, Error = failure::Error> {
+    // restic expects all-or-nothing writes, hence upload to a hidden file
+    // and move it into place once Jotta has it complete (with matching md5)
+    result(temp_path(&path)).and_then(move |temp| {
+        backend
+            .upload(&temp, futures::stream::once(Ok(body)))
+            .and_then(move |_| {
+                debug!("Moving {} into place at {}", temp, path);
+                backend.rename(&temp, &path)
+            })
+            .map(|_| HttpResponse::Ok().finish())
+    })
+}
 
-    req.state()
-        .backend
-        .delete(path)
-        .map_err(Error::from)
-        .and_then(|_| Ok(HttpResponse::Ok().finish()))
+/// Refuses an upload, which doesn't fit into the account anymore
+fn insufficient_storage(path: &JottaPath, size: u64) -> HttpResponse {
+    warn!(
+        "Refusing upload of {} bytes to {}, the quota is exceeded",
+        size, path
+    );
+    HttpResponse::build(StatusCode::INSUFFICIENT_STORAGE)
+        .content_type("text/plain; charset=utf-8")
+        .body(format!(
+            "{} bytes don't fit into the remaining quota of the Jottacloud account",
+            size
+        ))
 }
 
 pub fn upload(
@@ -184,19 +556,104 @@ pub fn upload(
 ) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
     info!("upload request {:?}", req);
 
-    let path = req.path();
+    use futures::future::Either;
+
+    let backend = backend(req);
+    let payload = req.payload().map_err(failure::Error::from);
+    let expected = expected_hash(req);
+    let may_replace = is_lock(req);
+    // Locks are tiny, and a full account needs them to run `restic prune`
+    let quota = if may_replace {
+        None
+    } else {
+        req.state().quota.clone()
+    };
+    let announced = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+
+    result(jotta_path(req)).and_then(move |path| {
+        // Don't wait for the body to find out, that Jotta will refuse it anyway
+        if let (Some(quota), Some(size)) = (quota.as_ref(), announced) {
+            if quota.exceeded_by(size) {
+                return Either::A(ok(insufficient_storage(&path, size)));
+            }
+        }
+
+        Either::B(
+            payload
+                .concat2()
+                .and_then(move |body| {
+                    // restic may send the body chunked, without announcing its size
+                    if quota
+                        .as_ref()
+                        .map_or(false, |q| q.exceeded_by(body.len() as u64))
+                    {
+                        return Either::A(ok(insufficient_storage(&path, body.len() as u64)));
+                    }
+
+                    if let Some(expected) = expected {
+                        let actual = sha256_hex(&body);
+                        if actual != expected {
+                            warn!(
+                                "Refusing upload to {}, its content hashes to {}",
+                                path, actual
+                            );
+                            return Either::A(ok(HttpResponse::BadRequest()
+                                .reason("Content doesn't match its name")
+                                .finish()));
+                        }
+                    }
 
-    req.state()
-        .backend
-        .upload(path, req.payload().map_err(failure::Error::from))
-        .map_err(Error::from)
-        .and_then(|_| Ok(HttpResponse::Ok().finish()))
+                    // restic never rewrites an object, a second upload to the
+                    // same name is either a retry or something going badly wrong
+                    let existing = if may_replace {
+                        Either::A(ok(None))
+                    } else {
+                        Either::B(lookup(&backend, &path))
+                    };
+
+                    Either::B(existing.and_then(move |existing| match existing {
+                        Some(Object::File(ref f)) if is_identical(f, &body) => {
+                            debug!("{} is already stored with identical content", path);
+                            Either::A(ok(HttpResponse::Ok().finish()))
+                        }
+                        Some(Object::File(_)) => {
+                            warn!("Refusing to overwrite {} with different content", path);
+                            Either::A(ok(HttpResponse::Forbidden()
+                                .reason("Object exists already")
+                                .finish()))
+                        }
+                        Some(Object::Folder(_)) => {
+                            warn!("Refusing to upload to {}, it's a folder", path);
+                            Either::A(ok(HttpResponse::Conflict()
+                                .reason("Object is a folder")
+                                .finish()))
+                        }
+                        None => {
+                            let refresh =
+                                quota.map(|quota| crate::quota::refresh(backend.clone(), quota));
+                            Either::B(store(backend, path, body).then(move |res| {
+                                // Successful or not, Jotta knows best how much space is left
+                                if let Some(refresh) = refresh {
+                                    actix::Arbiter::spawn(refresh);
+                                }
+                                res
+                            }))
+                        }
+                    }))
+                })
+                .or_else(respond),
+        )
+    })
 }
 
 pub fn list_dir(
     req: &HttpRequest<AppState>,
 ) -> impl Future<Item = HttpResponse, Error = actix_web::Error> {
-    //         Format:
+    //         Format v2:
     //        [
     //            {
     //                "name": "245bc4c430d393f74fbe7b13325e30dbde9fb0745e50caad57c446c93d20096b",
@@ -204,41 +661,51 @@ pub fn list_dir(
     //            },
     //            [...]
     //        ]
+    //
+    //         Format v1:
+    //        [
+    //            "245bc4c430d393f74fbe7b13325e30dbde9fb0745e50caad57c446c93d20096b",
+    //            [...]
+    //        ]
 
-    let path = req.path();
+    let backend = backend(req);
+    let v2 = wants_v2(req);
 
-    info!("listing of {}", path);
+    info!("listing of {} (v{})", req.path(), if v2 { 2 } else { 1 });
 
-    let build_answer = |dir: JottaFolder| -> Vec<DirListEntry> {
-        if dir.deleted.is_none() {
-            dir.files
-                .into_iter()
-                .filter_map(|file| match file.deleted {
-                    Some(_) => None,
-                    None => Some(DirListEntry::from(file)),
-                })
-                .collect()
+    let build_answer = move |dir: JottaFolder| -> HttpResponse {
+        let in_trash = dir.deleted.is_some();
+        let files = dir
+            .files
+            .into_iter()
+            .filter(move |f| !in_trash && is_visible(f));
+
+        if v2 {
+            HttpResponse::Ok()
+                .content_type(API_V2)
+                .json(files.map(DirListEntry::from).collect::<Vec<_>>())
         } else {
-            Vec::new()
+            HttpResponse::Ok()
+                .content_type(API_V1)
+                .json(files.map(|f| f.name).collect::<Vec<_>>())
         }
     };
 
-    req.state()
-        .backend
-        .query_object(path)
-        .and_then(move |obj| {
-            debug!("The following was returned:\n{:?}", obj);
-
-            match obj {
-                Object::Folder(dir) => ok(HttpResponse::Ok()
-                    .content_type("application/vnd.x.restic.rest.v2")
-                    .json(build_answer(dir))),
-                Object::File(_) => ok(HttpResponse::MethodNotAllowed()
-                    .reason("Not a directory")
-                    .finish()),
-            }
-        })
-        .map_err(Error::from)
+    result(jotta_path(req)).and_then(move |path| {
+        backend
+            .query_object(&path)
+            .and_then(move |obj| {
+                debug!("The following was returned:\n{:?}", obj);
+
+                match obj {
+                    Object::Folder(dir) => ok(build_answer(dir)),
+                    Object::File(_) => ok(HttpResponse::MethodNotAllowed()
+                        .reason("Not a directory")
+                        .finish()),
+                }
+            })
+            .or_else(respond)
+    })
 }
 
 /*
@@ -260,3 +727,147 @@ fn construct_response(
         )
     }
 }*/
+
+/// State with a Jotta client answering from the given cassette in fixtures/cassettes
+#[cfg(test)]
+pub(crate) fn replay_state(cassette: &str) -> AppState {
+    let cassette = jfs::Cassette::new(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/cassettes")
+            .join(cassette),
+    );
+    let client = JottaClient::new("oleidinger", "secret")
+        .unwrap()
+        .with_transport(jfs::Transport::Replay(cassette));
+
+    AppState {
+        backend: Some(Arc::new(client)),
+        config: Arc::new(crate::config::Config::default()),
+        scrub_report: None,
+        quota: None,
+    }
+}
+
+#[test]
+fn test_find_repos() {
+    // `team/project` is nested, `photos` holds none, `.git` has a `config` but
+    // no `data` folder. The folders of `team/project` and the deleted folder
+    // `old` aren't listed.
+    let backend = replay_state("find_repos").backend.unwrap();
+    let repos = find_repos(backend.clone(), JottaPath::root())
+        .wait()
+        .unwrap();
+
+    // The cassette's last exchange is only up next if nothing else was listed
+    backend
+        .list(&JottaPath::new("/photos").unwrap())
+        .wait()
+        .unwrap();
+
+    let paths = repos
+        .iter()
+        .map(|(path, _)| path.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, vec!["/team/project"]);
+}
+
+#[test]
+fn test_upload_hash_mismatch() {
+    use actix_web::test::TestRequest;
+
+    // Refused before talking to Jotta, hence no cassette
+    let response = TestRequest::with_state(replay_state("none"))
+        .method(Method::POST)
+        .uri(&format!("/test/data/{}", sha256_hex(b"restic")))
+        .set_payload("jotta")
+        .run_async(&|req: HttpRequest<AppState>| upload(&req))
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn test_refusals() {
+    use actix_web::test::TestRequest;
+
+    // Refused before talking to Jotta, hence no cassette
+    let run = |method: Method, uri: &str| {
+        TestRequest::with_state(replay_state("none"))
+            .method(method)
+            .uri(uri)
+            .run_async(&|req: HttpRequest<AppState>| main_handler(&req))
+            .unwrap()
+    };
+    let allow = |method: Method, uri: &str| {
+        let response = run(method, uri);
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        response.headers()[header::ALLOW]
+            .to_str()
+            .unwrap()
+            .to_owned()
+    };
+    let object = format!("/test/data/{}", sha256_hex(b"restic"));
+
+    assert_eq!(allow(Method::GET, "/test/"), "POST");
+    assert_eq!(allow(Method::POST, "/test/data/"), "GET");
+    assert_eq!(
+        allow(Method::PUT, "/test/config"),
+        "GET, HEAD, POST, DELETE"
+    );
+    assert_eq!(allow(Method::PATCH, &object), "GET, HEAD, POST, DELETE");
+
+    assert_eq!(
+        run(Method::GET, "/test/data/abc").status(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        run(Method::GET, "/test/blobs/abc").status(),
+        StatusCode::NOT_FOUND
+    );
+
+    assert_eq!(
+        run(Method::POST, "/test/").status(),
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        run(Method::POST, "/test/?create=false").status(),
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[test]
+fn test_list_versions() {
+    use actix_web::test::TestRequest;
+    use actix_web::Body;
+
+    let name = "2d2da19605a34e037dbe82173f98a992a530a5fdd53dad882f570d4ba204ef30";
+    let list = |accept: Option<&str>| {
+        let mut req = TestRequest::with_state(replay_state("list_data")).uri("/test/data/");
+        if let Some(accept) = accept {
+            req = req.header(header::ACCEPT, accept);
+        }
+        let response = req
+            .run_async(&|req: HttpRequest<AppState>| list_dir(&req))
+            .unwrap();
+
+        let content_type = response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let body = match response.body() {
+            Body::Binary(ref binary) => String::from_utf8(binary.as_ref().to_vec()).unwrap(),
+            _ => panic!("Expected a JSON body"),
+        };
+        (content_type, body)
+    };
+
+    // The temporary upload isn't listed
+    assert_eq!(list(None), (API_V1.to_owned(), format!("[\"{}\"]", name)));
+    assert_eq!(
+        list(Some(API_V2)),
+        (
+            API_V2.to_owned(),
+            format!("[{{\"name\":\"{}\",\"size\":10}}]", name)
+        )
+    );
+}
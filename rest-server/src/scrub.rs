@@ -0,0 +1,337 @@
+//! Re-verifies the objects stored in the repositories, slowly and in the background.
+//!
+//! Every object is downloaded (which checks Jotta's md5 sum) and its SHA-256 is
+//! compared to its name. Objects failing either check end up in the report.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix::prelude::*;
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use futures::Future;
+
+use actix_web::{HttpRequest, HttpResponse};
+use jfs::{
+    is_not_found, File as JottaFile, Folder as JottaFolder, IncompleteError, IntegrityError,
+    JottaClient, JottaPath,
+};
+
+use crate::layout::REPO_SUBDIRS;
+use crate::restic::{find_repos, is_visible, sha256_hex};
+use crate::AppState;
+
+#[derive(Clone, Debug, Serialize)]
+pub enum ProblemKind {
+    /// The content doesn't match the md5 sum Jotta has or the SHA-256 in its name
+    Corrupt,
+    /// The download failed
+    Unreadable,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    pub detail: String,
+    pub found: DateTime<Utc>,
+}
+
+/// What the scrubber found so far
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ScrubReport {
+    pub passes_completed: u64,
+    pub last_pass_finished: Option<DateTime<Utc>>,
+    /// Objects checked in the running pass
+    pub checked: u64,
+    /// Objects still to be checked in the running pass
+    pub pending: u64,
+    /// Problems by object path; cleared once the object verifies again
+    pub problems: BTreeMap<String, Problem>,
+}
+
+pub type SharedReport = Arc<Mutex<ScrubReport>>;
+
+/// Sleep as long as the transfer of size bytes should have taken at rate bytes per
+/// second. Each download runs at full speed, so the cap only holds on average.
+fn pause_after(size: usize, rate: u64) -> Duration {
+    let millis = size as u128 * 1000 / u128::from(rate.max(1));
+    Duration::from_millis(millis.min(u128::from(u64::max_value())) as u64)
+}
+
+pub struct Scrubber {
+    backend: Arc<JottaClient>,
+    root: JottaPath,
+    /// Pause between two passes
+    interval: Duration,
+    /// Bandwidth cap in bytes per second, on average
+    rate: u64,
+    report: SharedReport,
+    queue: VecDeque<(JottaPath, usize)>,
+}
+
+impl Scrubber {
+    pub fn new(
+        backend: Arc<JottaClient>,
+        root: JottaPath,
+        interval: Duration,
+        rate: u64,
+        report: SharedReport,
+    ) -> Scrubber {
+        Scrubber {
+            backend,
+            root,
+            interval,
+            rate: rate.max(1),
+            report,
+            queue: VecDeque::new(),
+        }
+    }
+
+    fn start_pass(&mut self, ctx: &mut Context<Self>) {
+        info!("Starting scrub pass below {}", self.root);
+
+        let fut = list_objects(self.backend.clone(), self.root.clone())
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(objects) => {
+                        let mut report = act.report.lock().unwrap();
+                        // Objects deleted since, i.e. by restic prune, won't be checked again
+                        let listed = objects
+                            .iter()
+                            .map(|(path, _)| path.to_string())
+                            .collect::<std::collections::HashSet<_>>();
+                        report.problems.retain(|path, _| listed.contains(path));
+
+                        act.queue = objects.into_iter().collect();
+                        report.checked = 0;
+                        report.pending = act.queue.len() as u64;
+                    }
+                    Err(e) => warn!("Couldn't list objects to scrub: {}", e),
+                }
+                act.check_next(ctx);
+                actix::fut::ok(())
+            });
+
+        ctx.spawn(fut);
+    }
+
+    fn finish_pass(&mut self, ctx: &mut Context<Self>) {
+        {
+            let mut report = self.report.lock().unwrap();
+            report.passes_completed += 1;
+            report.last_pass_finished = Some(Utc::now());
+
+            info!(
+                "Scrub pass finished, {} objects checked, {} problems known",
+                report.checked,
+                report.problems.len()
+            );
+        }
+
+        ctx.run_later(self.interval, |act, ctx| act.start_pass(ctx));
+    }
+
+    fn check_next(&mut self, ctx: &mut Context<Self>) {
+        let (path, size) = match self.queue.pop_front() {
+            Some(next) => next,
+            None => return self.finish_pass(ctx),
+        };
+
+        debug!("Scrubbing {}", path);
+
+        let fut = self
+            .backend
+            .download(&path)
+            .then(move |res| ok::<_, ()>((path, res)))
+            .into_actor(self)
+            .then(move |res, act, ctx| {
+                if let Ok((path, res)) = res {
+                    act.record(&path, verify(&path, res));
+                }
+
+                ctx.run_later(pause_after(size, act.rate), |act, ctx| act.check_next(ctx));
+                actix::fut::ok(())
+            });
+
+        ctx.spawn(fut);
+    }
+
+    fn record(&self, path: &JottaPath, outcome: Option<Problem>) {
+        let mut report = self.report.lock().unwrap();
+        report.checked += 1;
+        report.pending = self.queue.len() as u64;
+
+        match outcome {
+            Some(problem) => {
+                error!("Scrubbing {}: {:?} {}", path, problem.kind, problem.detail);
+                report.problems.insert(path.to_string(), problem);
+            }
+            None => {
+                report.problems.remove(&path.to_string());
+            }
+        }
+    }
+}
+
+impl Actor for Scrubber {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.start_pass(ctx);
+    }
+}
+
+/// Checks the outcome of a download, `None` means the object is fine
+fn verify<B: AsRef<[u8]>>(path: &JottaPath, res: Result<B, failure::Error>) -> Option<Problem> {
+    let problem = |kind, detail| {
+        Some(Problem {
+            kind,
+            detail,
+            found: Utc::now(),
+        })
+    };
+
+    match res {
+        Ok(data) => {
+            let name = path.file_name().unwrap_or_default();
+            let actual = sha256_hex(data.as_ref());
+            if name == "config" || actual == name {
+                None
+            } else {
+                problem(ProblemKind::Corrupt, format!("SHA-256 is {}", actual))
+            }
+        }
+        // Vanished in the meantime, i.e. by restic prune
+        Err(ref e) if is_not_found(e) => None,
+        // Being uploaded again
+        Err(ref e) if e.downcast_ref::<IncompleteError>().is_some() => None,
+        Err(ref e) if e.downcast_ref::<IntegrityError>().is_some() => {
+            problem(ProblemKind::Corrupt, e.to_string())
+        }
+        Err(e) => problem(ProblemKind::Unreadable, e.to_string()),
+    }
+}
+
+/// All objects restic can see in the repositories at or below root, with their size
+fn list_objects(
+    backend: Arc<JottaClient>,
+    root: JottaPath,
+) -> impl Future<Item = Vec<(JottaPath, usize)>, Error = failure::Error> {
+    find_repos(backend.clone(), root).and_then(move |repos| {
+        let repos = repos
+            .into_iter()
+            .map(|(repo, dir)| list_repo(backend.clone(), repo, dir))
+            .collect::<Vec<_>>();
+
+        join_all(repos).map(|lists| lists.into_iter().flatten().collect())
+    })
+}
+
+fn list_repo(
+    backend: Arc<JottaClient>,
+    repo: JottaPath,
+    dir: JottaFolder,
+) -> impl Future<Item = Vec<(JottaPath, usize)>, Error = failure::Error> {
+    let objects = |path: &JottaPath, files: Vec<JottaFile>| {
+        files
+            .into_iter()
+            .filter(is_visible)
+            .filter_map(|f| path.join(&f.name).ok().map(|p| (p, f.size())))
+            .collect::<Vec<_>>()
+    };
+
+    let mut result = objects(&repo, dir.files)
+        .into_iter()
+        .filter(|(p, _)| p.file_name() == Some("config"))
+        .collect::<Vec<_>>();
+
+    let subdirs = REPO_SUBDIRS
+        .iter()
+        .filter_map(|subdir| repo.join(subdir).ok())
+        .map(|path| {
+            backend
+                .list(&path)
+                .map(move |dir| objects(&path, dir.files))
+                .or_else(|_| Ok::<_, failure::Error>(Vec::new()))
+        })
+        .collect::<Vec<_>>();
+
+    join_all(subdirs).map(move |lists| {
+        result.extend(lists.into_iter().flatten());
+        result
+    })
+}
+
+/// The report as JSON, 404 if scrubbing is disabled
+pub fn report(req: &HttpRequest<AppState>) -> HttpResponse {
+    match req.state().scrub_report {
+        Some(ref report) => HttpResponse::Ok().json(&*report.lock().unwrap()),
+        None => HttpResponse::NotFound()
+            .reason("Scrubbing is disabled")
+            .finish(),
+    }
+}
+
+#[test]
+fn test_verify() {
+    use failure::err_msg;
+    use jfs::JottaError;
+
+    let object = |name: &str| JottaPath::new(&format!("/test/data/{}", name)).unwrap();
+    let kind = |problem: Option<Problem>| problem.map(|p| format!("{:?}", p.kind));
+    let name = sha256_hex(b"restic");
+
+    assert!(verify(&object(&name), Ok(b"restic")).is_none());
+    assert!(verify(&JottaPath::new("/test/config").unwrap(), Ok(b"anything")).is_none());
+    assert_eq!(
+        kind(verify(&object(&name), Ok(b"jotta"))),
+        Some("Corrupt".to_owned())
+    );
+
+    let integrity = IntegrityError {
+        path: object(&name).to_string(),
+        expected: "5c372a32c9ae748a4c040ebadc51a829".to_owned(),
+        actual: "3e6f3ba3f0a1e9d4c7e8b5d1a2c4f6e8".to_owned(),
+    };
+    assert_eq!(
+        kind(verify::<&[u8]>(&object(&name), Err(integrity.into()))),
+        Some("Corrupt".to_owned())
+    );
+
+    let not_found = JottaError {
+        code: 404,
+        ..Default::default()
+    };
+    assert!(verify::<&[u8]>(&object(&name), Err(not_found.into())).is_none());
+
+    let failing = JottaError {
+        code: 500,
+        ..Default::default()
+    };
+    assert_eq!(
+        kind(verify::<&[u8]>(&object(&name), Err(failing.into()))),
+        Some("Unreadable".to_owned())
+    );
+    assert_eq!(
+        kind(verify::<&[u8]>(&object(&name), Err(err_msg("timeout")))),
+        Some("Unreadable".to_owned())
+    );
+}
+
+#[test]
+fn test_pause_after() {
+    assert_eq!(pause_after(0, 1024), Duration::from_secs(0));
+    assert_eq!(
+        pause_after(1024 * 1024, 1024 * 1024),
+        Duration::from_secs(1)
+    );
+    assert_eq!(pause_after(512, 1024), Duration::from_millis(500));
+    // Neither overflows nor divides by zero
+    assert_eq!(
+        pause_after(usize::max_value(), 1),
+        Duration::from_millis(u64::max_value())
+    );
+    assert_eq!(pause_after(10, 0), Duration::from_secs(10));
+}
@@ -0,0 +1,55 @@
+//! A single HTTP connection over stdin/stdout, as used by restic's rclone backend
+
+use std::io::{self, Read, Write};
+
+use futures::Poll;
+use tokio_file_unix::File;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_reactor::PollEvented2;
+
+pub struct Stdio {
+    stdin: PollEvented2<File<io::Stdin>>,
+    stdout: PollEvented2<File<io::Stdout>>,
+}
+
+impl Stdio {
+    pub fn new() -> io::Result<Stdio> {
+        let handle = tokio_reactor::Handle::default();
+
+        Ok(Stdio {
+            stdin: File::new_nb(io::stdin())?.into_io(&handle)?,
+            stdout: File::new_nb(io::stdout())?.into_io(&handle)?,
+        })
+    }
+}
+
+impl Read for Stdio {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdin.read(buf)?;
+
+        // restic is done with us
+        if read == 0 && !buf.is_empty() {
+            info!("stdin closed, shutting down");
+            actix::System::current().stop();
+        }
+        Ok(read)
+    }
+}
+
+impl Write for Stdio {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdout.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+}
+
+impl AsyncRead for Stdio {}
+
+impl AsyncWrite for Stdio {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.stdout.shutdown()
+    }
+}
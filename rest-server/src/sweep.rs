@@ -0,0 +1,296 @@
+//! Removes what failed uploads leave behind, in the background.
+//!
+//! These are the server's own temporaries, i.e. after a crash, and with
+//! `--incomplete-grace` files whose upload to Jotta never completed. Sweeping
+//! runs at startup and then periodically, never while answering restic.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::prelude::*;
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use futures::Future;
+
+use jfs::{File as JottaFile, Folder as JottaFolder, JottaClient, JottaPath, Object};
+
+use crate::config::Config;
+use crate::restic::{create_base, find_repos, REPO_SUBDIRS, TEMP_PREFIX};
+
+/// Temporaries younger than this may still be uploaded to, i.e. by another
+/// instance of the server; far beyond the time an upload may take in jfs
+const TEMP_GRACE: Duration = Duration::from_secs(60 * 60);
+
+/// Whether the file's latest revision is older than the grace period
+fn is_idle(file: &JottaFile, now: DateTime<Utc>, grace: Duration) -> bool {
+    file.latest_revision
+        .as_ref()
+        .or_else(|| file.current_revision.as_ref())
+        .map_or(false, |r| {
+            now.signed_duration_since(r.updated)
+                .to_std()
+                .map_or(false, |age| age > grace)
+        })
+}
+
+/// A temporary upload, which has been abandoned, i.e. by a crash
+fn is_stale_temporary(file: &JottaFile, now: DateTime<Utc>) -> bool {
+    file.deleted.is_none() && file.name.starts_with(TEMP_PREFIX) && is_idle(file, now, TEMP_GRACE)
+}
+
+/// A file, whose upload never completed and didn't make progress within the grace period
+fn is_stale_incomplete(file: &JottaFile, now: DateTime<Utc>, grace: Duration) -> bool {
+    file.deleted.is_none() && file.current_revision.is_none() && is_idle(file, now, grace)
+}
+
+/// What may be removed in a repository
+#[derive(Debug, PartialEq)]
+enum Sweep {
+    /// Read-only repositories are never changed
+    Nothing,
+    Temporaries,
+    /// Temporaries and incomplete uploads older than the grace period
+    Incomplete(Duration),
+}
+
+fn what_to_sweep(config: &Config, repo: &str) -> Sweep {
+    if config.is_read_only(repo) {
+        Sweep::Nothing
+    } else {
+        // restic never sees incomplete uploads, but append-only means nothing goes
+        match config.incomplete_grace {
+            Some(grace) if !config.is_append_only(repo) => Sweep::Incomplete(grace),
+            _ => Sweep::Temporaries,
+        }
+    }
+}
+
+/// The repository's name as restic knows it, i.e. relative to the base folder
+fn repo_name(base: &JottaPath, repo: &JottaPath) -> String {
+    repo.segments()[base.segments().len()..].join("/")
+}
+
+/// Cleans up all repositories below the base folder
+pub fn sweep(backend: Arc<JottaClient>, config: Arc<Config>) -> impl Future<Item = (), Error = ()> {
+    let base = config.base.clone();
+
+    info!("Looking for stale uploads below {}", base);
+
+    find_repos(backend.clone(), base.clone())
+        .and_then(move |repos| {
+            let sweeps = repos
+                .into_iter()
+                .filter_map(
+                    |(repo, dir)| match what_to_sweep(&config, &repo_name(&base, &repo)) {
+                        Sweep::Nothing => {
+                            debug!("Not sweeping read-only repository {}", repo);
+                            None
+                        }
+                        Sweep::Temporaries => Some(sweep_repo(backend.clone(), repo, dir, None)),
+                        Sweep::Incomplete(grace) => {
+                            Some(sweep_repo(backend.clone(), repo, dir, Some(grace)))
+                        }
+                    },
+                )
+                .collect::<Vec<_>>();
+
+            join_all(sweeps)
+        })
+        .map(|_| ())
+        .map_err(|e| warn!("Couldn't sweep stale uploads: {}", e))
+}
+
+fn sweep_repo(
+    backend: Arc<JottaClient>,
+    repo: JottaPath,
+    dir: JottaFolder,
+    incomplete_grace: Option<Duration>,
+) -> impl Future<Item = (), Error = failure::Error> {
+    let mut folders = vec![(repo.clone(), dir)];
+    let subdirs = REPO_SUBDIRS
+        .iter()
+        .filter_map(|subdir| repo.join(subdir).ok())
+        .map(|path| {
+            backend
+                .list(&path)
+                .map(move |dir| Some((path, dir)))
+                .or_else(|_| Ok::<_, failure::Error>(None))
+        })
+        .collect::<Vec<_>>();
+
+    join_all(subdirs).and_then(move |listed| {
+        folders.extend(listed.into_iter().filter_map(|x| x));
+
+        let now = Utc::now();
+        let removals = folders
+            .into_iter()
+            .flat_map(|(path, dir)| dir.files.into_iter().map(move |f| (path.clone(), f)))
+            .filter_map(|(dir, f)| {
+                let path = dir.join(&f.name).ok()?;
+
+                let removal: Box<dyn Future<Item = Object, Error = failure::Error>> =
+                    if is_stale_temporary(&f, now) {
+                        info!("Deleting stale temporary upload {}", path);
+                        Box::new(backend.delete(&path))
+                    } else if incomplete_grace
+                        .map_or(false, |grace| is_stale_incomplete(&f, now, grace))
+                    {
+                        debug!("Incomplete upload {} is stale", path);
+                        Box::new(backend.abandon_incomplete(&path))
+                    } else {
+                        return None;
+                    };
+
+                Some(removal.then(move |res| {
+                    if let Err(e) = res {
+                        warn!("Couldn't remove {}: {}", path, e);
+                    }
+                    Ok::<_, failure::Error>(())
+                }))
+            })
+            .collect::<Vec<_>>();
+
+        join_all(removals).map(|_| ())
+    })
+}
+
+/// Sweeps at startup, after creating the base folder, and then as often as
+/// the grace period of incomplete uploads, if any. Idle when read-only.
+pub struct Sweeper {
+    backend: Arc<JottaClient>,
+    config: Arc<Config>,
+    /// A sweep is under way, the next one has to wait
+    running: bool,
+}
+
+impl Sweeper {
+    pub fn new(backend: Arc<JottaClient>, config: Arc<Config>) -> Sweeper {
+        Sweeper {
+            backend,
+            config,
+            running: false,
+        }
+    }
+
+    fn run<F>(&mut self, ctx: &mut Context<Self>, fut: F)
+    where
+        F: Future<Item = (), Error = ()> + 'static,
+    {
+        if self.running {
+            debug!("The last sweep is still running, skipping this one");
+            return;
+        }
+        self.running = true;
+
+        ctx.spawn(fut.into_actor(self).then(|_, act, _| {
+            act.running = false;
+            actix::fut::ok(())
+        }));
+    }
+}
+
+impl Actor for Sweeper {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if self.config.read_only {
+            debug!("Read-only, neither creating the base folder nor sweeping");
+            return;
+        }
+
+        let backend = self.backend.clone();
+        let config = self.config.clone();
+        let prepare = create_base(backend.clone(), config.base.clone())
+            .and_then(move |_| sweep(backend, config));
+        self.run(ctx, prepare);
+
+        if let Some(grace) = self.config.incomplete_grace {
+            ctx.run_interval(grace, |act, ctx| {
+                let fut = sweep(act.backend.clone(), act.config.clone());
+                act.run(ctx, fut);
+            });
+        }
+    }
+}
+
+#[test]
+fn test_stale_temporaries() {
+    let now = "2019-01-20T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+    let file = |name: &str, updated: &str| {
+        format!(
+            r#"<file name="{}" uuid="9b009f64-8e6f-4bea-bd82-510edd7f645e">
+                <latestRevision>
+                    <number>1</number>
+                    <state>INCOMPLETE</state>
+                    <created>{}</created>
+                    <modified>{}</modified>
+                    <mime>application/octet-stream</mime>
+                    <md5>a3ee7c06817513862b5b3d9b758899af</md5>
+                    <updated>{}</updated>
+                </latestRevision>
+            </file>"#,
+            name, updated, updated, updated
+        )
+        .parse::<JottaFile>()
+        .unwrap()
+    };
+    let temp = format!("{}config", TEMP_PREFIX);
+
+    // Someone may still upload to it
+    assert!(!is_stale_temporary(
+        &file(&temp, "2019-01-20-T11:50:00Z"),
+        now
+    ));
+    assert!(is_stale_temporary(
+        &file(&temp, "2019-01-20-T10:00:00Z"),
+        now
+    ));
+    assert!(!is_stale_temporary(
+        &file("config", "2019-01-20-T10:00:00Z"),
+        now
+    ));
+
+    let grace = Duration::from_secs(30 * 60);
+    assert!(is_stale_incomplete(
+        &file("config", "2019-01-20-T11:00:00Z"),
+        now,
+        grace
+    ));
+    assert!(!is_stale_incomplete(
+        &file("config", "2019-01-20-T11:50:00Z"),
+        now,
+        grace
+    ));
+}
+
+#[test]
+fn test_what_to_sweep() {
+    let grace = Duration::from_secs(60);
+    let config = Config {
+        incomplete_grace: Some(grace),
+        append_only_repos: vec!["archive".to_owned()],
+        read_only_repos: vec!["restore".to_owned()],
+        ..Default::default()
+    };
+
+    assert_eq!(
+        what_to_sweep(&config, "team/project"),
+        Sweep::Incomplete(grace)
+    );
+    assert_eq!(what_to_sweep(&config, "archive"), Sweep::Temporaries);
+    assert_eq!(what_to_sweep(&config, "restore"), Sweep::Nothing);
+
+    let config = Config {
+        read_only: true,
+        ..config
+    };
+    assert_eq!(what_to_sweep(&config, "team/project"), Sweep::Nothing);
+
+    assert_eq!(
+        repo_name(
+            &JottaPath::new("/Backups").unwrap(),
+            &JottaPath::new("/Backups/team/project").unwrap()
+        ),
+        "team/project"
+    );
+}
@@ -0,0 +1,126 @@
+//! TLS termination, optionally requiring client certificates, with reload on SIGHUP
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use actix::actors::signal;
+use actix::prelude::*;
+use actix_web::server::{PauseServer, Server, StopServer};
+use failure::Error;
+use openssl::pkey::PKey;
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslMethod, SslVerifyMode};
+use openssl::x509::X509;
+
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain
+    pub cert: PathBuf,
+    /// PEM encoded private key
+    pub key: PathBuf,
+    /// Only accept clients with a certificate signed by one of these CAs
+    pub client_ca: Option<PathBuf>,
+}
+
+/// The files of a `TlsConfig`, read once, so all servers started from them
+/// use the same certificates, even if the files change in the meantime
+#[derive(Clone)]
+pub struct Certificates {
+    key: Vec<u8>,
+    chain: Vec<u8>,
+    client_ca: Option<Vec<u8>>,
+}
+
+impl Certificates {
+    /// Reads the files and makes sure they make up a working acceptor
+    pub fn load(config: &TlsConfig) -> Result<Certificates, Error> {
+        let certificates = Certificates {
+            key: fs::read(&config.key)?,
+            chain: fs::read(&config.cert)?,
+            client_ca: config.client_ca.as_ref().map(fs::read).transpose()?,
+        };
+        certificates.acceptor()?;
+
+        Ok(certificates)
+    }
+
+    pub fn acceptor(&self) -> Result<SslAcceptorBuilder, Error> {
+        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+        builder.set_private_key(&PKey::private_key_from_pem(&self.key)?)?;
+
+        let mut chain = X509::stack_from_pem(&self.chain)?.into_iter();
+        let cert = chain
+            .next()
+            .ok_or_else(|| failure::err_msg("No certificate found"))?;
+        builder.set_certificate(&cert)?;
+        for intermediate in chain {
+            builder.add_extra_chain_cert(intermediate)?;
+        }
+        builder.check_private_key()?;
+
+        if let Some(ref ca) = self.client_ca {
+            for cert in X509::stack_from_pem(ca)? {
+                builder.cert_store_mut().add_cert(cert)?;
+            }
+            builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        }
+
+        Ok(builder)
+    }
+}
+
+/// Running transfers may finish when the server is replaced, uploads to Jotta take up to 10 minutes
+const RELOAD_GRACE: Duration = Duration::from_secs(600);
+
+/// Starts a new server with the certificates on SIGHUP. The old one stops accepting
+/// connections, once the new one does, and stops after `RELOAD_GRACE`.
+pub struct Reloader<F>
+where
+    F: Fn(&Certificates) -> Result<Addr<Server>, Error> + 'static,
+{
+    pub config: TlsConfig,
+    pub start: F,
+    pub server: Addr<Server>,
+}
+
+impl<F> Actor for Reloader<F>
+where
+    F: Fn(&Certificates) -> Result<Addr<Server>, Error> + 'static,
+{
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let signals = System::current().registry().get::<signal::ProcessSignals>();
+        signals.do_send(signal::Subscribe(ctx.address().recipient()));
+    }
+}
+
+impl<F> Handler<signal::Signal> for Reloader<F>
+where
+    F: Fn(&Certificates) -> Result<Addr<Server>, Error> + 'static,
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: signal::Signal, ctx: &mut Self::Context) {
+        if msg.0 != signal::SignalType::Hup {
+            return;
+        }
+
+        // Keep serving the old certificates, if the new ones are broken
+        let server = match Certificates::load(&self.config).and_then(|c| (self.start)(&c)) {
+            Ok(server) => server,
+            Err(e) => {
+                error!("Couldn't reload TLS certificates: {}", e);
+                return;
+            }
+        };
+
+        info!("Reloaded TLS certificates, the old server finishes running transfers");
+
+        let old = std::mem::replace(&mut self.server, server);
+        old.do_send(PauseServer);
+        ctx.run_later(RELOAD_GRACE, move |_, _| {
+            Arbiter::spawn(old.send(StopServer { graceful: true }).then(|_| Ok(())));
+        });
+    }
+}